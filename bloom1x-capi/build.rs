@@ -0,0 +1,10 @@
+fn main() {
+    //the header is kept current by the c_api_header_is_current test of bloom-1x,
+    //it is copied next to the libraries so that C builds find both in target/<profile>
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let header = format!("{crate_dir}/../include/bloom1x.h");
+    let profile_dir = out_dir.ancestors().nth(3).unwrap();
+    std::fs::copy(&header, profile_dir.join("bloom1x.h")).unwrap();
+    println!("cargo:rerun-if-changed={header}");
+}
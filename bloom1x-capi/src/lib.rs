@@ -0,0 +1,4 @@
+//! the C API of bloom-1x built as a shared and a static library:
+//! cargo build --release -p bloom1x-capi gives target/release/libbloom1x.{so,a} and bloom1x.h
+
+pub use bloom_1x::capi::*;
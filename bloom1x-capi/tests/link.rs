@@ -0,0 +1,28 @@
+//! builds tests/smoke.c against the static library and header cargo just produced
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn c_program_links_and_runs() {
+    //target/<profile>/deps/link-<hash> sits two levels below the libraries
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap();
+    let library = profile_dir.join("libbloom1x.a");
+    assert!(library.exists(), "{} was not built", library.display());
+    let program = profile_dir.join("bloom1x-capi-smoke");
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/smoke.c");
+    let status = Command::new(compiler)
+        .arg(source)
+        .arg("-I")
+        .arg(profile_dir)
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("no C compiler, set CC");
+    assert!(status.success());
+    let status = Command::new(&program).status().unwrap();
+    assert!(status.code() == Some(0), "check {:?} of smoke.c failed", status.code());
+}
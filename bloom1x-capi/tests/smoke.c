@@ -0,0 +1,29 @@
+/* links against libbloom1x.a through bloom1x.h, exits with the number of the first failed check */
+#include "bloom1x.h"
+#include <stdlib.h>
+
+int main(void) {
+    Bloom1X *filter = NULL, *copy = NULL;
+    Bloom1Counter *counter = NULL;
+    uint8_t found = 0;
+    size_t size = 0;
+    if (b1x_filter_new(4, 1000, 64, 96, 0, false, &filter) != B1X_STATUS_INVALID_ARGUMENT) return 1;
+    if (b1x_filter_new(4, 4096, 64, 96, 0, false, &filter) != B1X_STATUS_OK) return 2;
+    for (uint64_t i = 0; i < 1000; i++) b1x_filter_insert_u64(filter, i, &found);
+    if (b1x_filter_query_u64(filter, 5, &found) != B1X_STATUS_OK || found != 1) return 3;
+    if (b1x_filter_query_u64(NULL, 5, &found) != B1X_STATUS_NULL_POINTER) return 4;
+    b1x_filter_serialize(filter, NULL, 0, &size);
+    uint8_t *bytes = malloc(size);
+    if (b1x_filter_serialize(filter, bytes, size, &size) != B1X_STATUS_OK) return 5;
+    if (b1x_filter_deserialize(bytes, size, &copy) != B1X_STATUS_OK) return 6;
+    if (b1x_filter_query_u64(copy, 999, &found) != B1X_STATUS_OK || found != 1) return 7;
+    if (b1x_counter_new(4, 4096, 64, 96, 4, 0, false, &counter) != B1X_STATUS_OK) return 8;
+    b1x_counter_insert_u64(counter, 1, &found);
+    if (b1x_counter_remove_u64(counter, 1) != B1X_STATUS_OK) return 9;
+    if (b1x_counter_remove_u64(counter, 1) == B1X_STATUS_OK) return 10;
+    b1x_filter_free(filter);
+    b1x_filter_free(copy);
+    b1x_counter_free(counter);
+    free(bytes);
+    return 0;
+}
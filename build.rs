@@ -0,0 +1,23 @@
+fn main() {
+    //the C header of the capi feature is generated into OUT_DIR on every build of that feature,
+    //the copy in include/bloom1x.h is only rewritten when BLOOM1X_UPDATE_HEADER is set
+    //and the c_api_header_is_current test fails while it is stale
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).unwrap();
+        let header = cbindgen::Builder::new()
+            .with_src(format!("{crate_dir}/src/capi.rs"))
+            .with_config(config)
+            .generate()
+            .expect("unable to generate the C header");
+        header.write_to_file(format!("{out_dir}/bloom1x.h"));
+        if std::env::var_os("BLOOM1X_UPDATE_HEADER").is_some() {
+            header.write_to_file(format!("{crate_dir}/include/bloom1x.h"));
+        }
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=BLOOM1X_UPDATE_HEADER");
+    }
+}
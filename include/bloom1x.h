@@ -0,0 +1,153 @@
+#ifndef BLOOM1X_H
+#define BLOOM1X_H
+
+/* generated by cbindgen from src/capi.rs with the capi feature, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+typedef struct Bloom1X Bloom1X;
+typedef struct Bloom1Counter Bloom1Counter;
+
+// status code returned by every function of the C API
+typedef enum B1xStatus {
+  B1X_STATUS_OK = 0,
+  // a required pointer was null
+  B1X_STATUS_NULL_POINTER = 1,
+  // invalid geometry, counter width, index mode or key longer than 48 bytes
+  B1X_STATUS_INVALID_ARGUMENT = 2,
+  // the output buffer is too small, the required size was written to the size out pointer
+  B1X_STATUS_BUFFER_TOO_SMALL = 3,
+  // the serialized filter could not be decoded
+  B1X_STATUS_DECODE_ERROR = 4,
+  // removal of a key the filter does not contain
+  B1X_STATUS_NOT_PRESENT = 5,
+  // removal of a key the filter shows was never inserted
+  B1X_STATUS_NOT_INSERTED = 6,
+  // the library panicked, the filter should be considered corrupted
+  B1X_STATUS_PANIC = 7,
+} B1xStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// creates a Bloom1X, index_mode is 0 for modulo and 1 for multiply-shift
+// the filter must be released with b1x_filter_free
+enum B1xStatus b1x_filter_new(size_t k,
+                              size_t l,
+                              size_t w,
+                              size_t h,
+                              uint8_t index_mode,
+                              bool partitioned,
+                              Bloom1X **filter);
+
+// releases a filter, null is ignored
+void b1x_filter_free(Bloom1X *filter);
+
+// inserts a key of at most 48 bytes, found is set to 1 if it was already present
+enum B1xStatus b1x_filter_insert_bytes(Bloom1X *filter,
+                                       const uint8_t *key,
+                                       size_t len,
+                                       uint8_t *found);
+
+enum B1xStatus b1x_filter_insert_u64(Bloom1X *filter, uint64_t key, uint8_t *found);
+
+// found is set to 1 if the key of at most 48 bytes may be present and to 0 otherwise
+enum B1xStatus b1x_filter_query_bytes(const Bloom1X *filter,
+                                      const uint8_t *key,
+                                      size_t len,
+                                      uint8_t *found);
+
+enum B1xStatus b1x_filter_query_u64(const Bloom1X *filter, uint64_t key, uint8_t *found);
+
+// writes the row of a key and the k bit indexes inside it, without reading the filter
+// bit_indexes must hold at least k entries
+enum B1xStatus b1x_filter_search_bytes(const Bloom1X *filter,
+                                       const uint8_t *key,
+                                       size_t len,
+                                       uint32_t *row_index,
+                                       uint32_t *bit_indexes,
+                                       size_t capacity);
+
+enum B1xStatus b1x_filter_search_u64(const Bloom1X *filter,
+                                     uint64_t key,
+                                     uint32_t *row_index,
+                                     uint32_t *bit_indexes,
+                                     size_t capacity);
+
+// serializes the filter into buffer, written receives the size of the encoding
+// a null buffer or a short capacity returns BufferTooSmall, which can be used to query the size
+enum B1xStatus b1x_filter_serialize(const Bloom1X *filter,
+                                    uint8_t *buffer,
+                                    size_t capacity,
+                                    size_t *written);
+
+// rebuilds a filter serialized by b1x_filter_serialize
+enum B1xStatus b1x_filter_deserialize(const uint8_t *buffer, size_t len, Bloom1X **filter);
+
+// creates a Bloom1Counter with counters of 2, 4, 8 or 16 bits
+// the filter must be released with b1x_counter_free
+enum B1xStatus b1x_counter_new(size_t k,
+                               size_t l,
+                               size_t w,
+                               size_t h,
+                               uint8_t counter_bits,
+                               uint8_t index_mode,
+                               bool partitioned,
+                               Bloom1Counter **filter);
+
+// releases a counting filter, null is ignored
+void b1x_counter_free(Bloom1Counter *filter);
+
+// increments the counters of a key of at most 48 bytes, found is set to 1 if it was already present
+enum B1xStatus b1x_counter_insert_bytes(Bloom1Counter *filter,
+                                        const uint8_t *key,
+                                        size_t len,
+                                        uint8_t *found);
+
+enum B1xStatus b1x_counter_insert_u64(Bloom1Counter *filter, uint64_t key, uint8_t *found);
+
+// decrements the counters of a key of at most 48 bytes inserted before
+enum B1xStatus b1x_counter_remove_bytes(Bloom1Counter *filter, const uint8_t *key, size_t len);
+
+enum B1xStatus b1x_counter_remove_u64(Bloom1Counter *filter, uint64_t key);
+
+// found is set to 1 if the key of at most 48 bytes may be present and to 0 otherwise
+enum B1xStatus b1x_counter_query_bytes(const Bloom1Counter *filter,
+                                       const uint8_t *key,
+                                       size_t len,
+                                       uint8_t *found);
+
+enum B1xStatus b1x_counter_query_u64(const Bloom1Counter *filter, uint64_t key, uint8_t *found);
+
+// writes the row of a key and the k counter indexes inside it, counter_indexes must hold at least k entries
+enum B1xStatus b1x_counter_search_bytes(const Bloom1Counter *filter,
+                                        const uint8_t *key,
+                                        size_t len,
+                                        uint32_t *row_index,
+                                        uint32_t *counter_indexes,
+                                        size_t capacity);
+
+enum B1xStatus b1x_counter_search_u64(const Bloom1Counter *filter,
+                                      uint64_t key,
+                                      uint32_t *row_index,
+                                      uint32_t *counter_indexes,
+                                      size_t capacity);
+
+// serializes the counting filter, see b1x_filter_serialize
+enum B1xStatus b1x_counter_serialize(const Bloom1Counter *filter,
+                                     uint8_t *buffer,
+                                     size_t capacity,
+                                     size_t *written);
+
+// rebuilds a counting filter serialized by b1x_counter_serialize
+enum B1xStatus b1x_counter_deserialize(const uint8_t *buffer, size_t len, Bloom1Counter **filter);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BLOOM1X_H */
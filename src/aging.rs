@@ -0,0 +1,74 @@
+//! periodic decay of the counters of a Bloom1Counter
+//! aged counters turn the filter into a frequency sketch over a recent window of insertions
+
+use crate::bloom_counter::Bloom1Counter;
+
+/// operation applied to every counter of an aged row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aging {
+    /// divides every counter by two, rounding down
+    Halve,
+    /// subtracts a constant from every counter, with a floor at zero
+    Subtract(u64)
+}
+
+impl Aging {
+    /// value of a counter after aging
+    #[inline(always)]
+    pub(crate) fn apply(self, count: u64) -> u64 {
+        match self {
+            Aging::Halve => count / 2,
+            Aging::Subtract(amount) => count.saturating_sub(amount)
+        }
+    }
+}
+
+/// triggers the aging of a Bloom1Counter after a fixed number of insertions
+/// with a fraction below 1 the work is spread: every period * fraction insertions
+/// the next fraction of the rows is aged, so all rows are still aged once per period
+#[derive(Debug, Clone)]
+pub struct AgingScheduler {
+    /// operation applied to the aged counters
+    aging: Aging,
+    /// fraction of the rows aged at each step
+    fraction: f64,
+    /// number of insertions between two aging steps
+    step: u64,
+    /// insertions recorded since the last aging step
+    pending: u64
+}
+
+impl AgingScheduler {
+    /// ages the whole filter every `period` insertions
+    pub fn new(period: u64, aging: Aging) -> Self {
+        assert!(period > 0);
+        Self { aging, fraction: 1.0, step: period, pending: 0 }
+    }
+
+    /// spreads each aging pass over 1/fraction smaller steps
+    pub fn with_fraction(mut self, fraction: f64) -> Self {
+        assert!(fraction > 0.0 && fraction <= 1.0);
+        let period = self.step as f64 / self.fraction;
+        self.fraction = fraction;
+        self.step = ((period * fraction) as u64).max(1);
+        self
+    }
+
+    /// records a number of insertions and ages the filter when a step is due
+    /// returns true if the filter was aged
+    pub fn record_insertions(&mut self, filter: &mut Bloom1Counter, count: u64) -> bool {
+        self.pending += count;
+        if self.pending < self.step {
+            return false;
+        }
+        while self.pending >= self.step {
+            self.pending -= self.step;
+            filter.age_fraction(self.aging, self.fraction);
+        }
+        true
+    }
+
+    pub fn record_insertion(&mut self, filter: &mut Bloom1Counter) -> bool {
+        self.record_insertions(filter, 1)
+    }
+}
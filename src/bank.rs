@@ -0,0 +1,238 @@
+//! bank of Bloom1X filters sharing one geometry, stored bit-sliced:
+//! every bit position of every row keeps one bit per member filter, packed into u64 words,
+//! so a single row read answers a query for 64 members per word at once
+
+use crate::bloom::Bloom1X;
+use crate::digest::{self, KeyDigest};
+use crate::index::{self, IndexMode};
+
+#[derive(Debug)]
+pub struct Bloom1XBank {
+    /// number of hashes
+    k: usize,
+    /// number of rows in every member
+    l: usize,
+    /// width of a row in bits
+    w: usize,
+    /// number of bits to be extracted from hash for computing the row index
+    row_bits: usize,
+    /// number of bits to be extracted from hash for each sub-hash
+    hash_bits: usize,
+    /// how sub-hashes are mapped onto positions inside a row
+    index_mode: IndexMode,
+    /// whether every sub-hash indexes its own segment of the row
+    partitioned: bool,
+    /// number of u64 words kept per bit position, the bank holds 64 members per word
+    words: usize,
+    /// word j of bit position b in row r lives at (r * w + b) * words + j
+    /// and holds that bit for members 64*j up to 64*j + 63
+    slices: Box<[u64]>,
+    /// occupied member slots, one bit per slot
+    members: Vec<u64>
+}
+
+impl Bloom1XBank {
+    /// an empty bank for members built with Bloom1X::new(k, l, w, h)
+    /// it starts with room for 64 members and grows as members are added
+    pub fn new(k: usize, l: usize, w: usize, h: usize) -> Self {
+        //same limits as the members themselves
+        assert!(w >= 32 && w.is_multiple_of(8) && w <= 256);
+        assert!(l >= 1024 && l < u32::MAX as usize);
+        assert!(k >= 2);
+        assert!(h > 64);
+
+        let row_bits = index::row_bits(k, l, h);
+        assert!(row_bits <= 32, "non power of two row counts are limited to 2^24 rows");
+        assert!(row_bits < h);
+        assert!((h - row_bits).is_multiple_of(k));
+        let hash_bits = (h - row_bits) / k;
+
+        let slices = vec![0u64; l * w].into_boxed_slice();
+        Self { k, l, w, row_bits, hash_bits, index_mode: IndexMode::default(), partitioned: false, words: 1, slices, members: vec![0] }
+    }
+
+    /// index mode of the members, must be chosen before any member is added
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// members use partitioned rows, must be chosen before any member is added
+    pub fn with_partitioned_rows(mut self) -> Self {
+        assert!(self.w.is_multiple_of(self.k));
+        self.partitioned = true;
+        self
+    }
+
+    /// number of member slots available before the bank grows
+    pub fn capacity(&self) -> usize {
+        self.words * 64
+    }
+
+    /// number of members currently in the bank
+    pub fn len(&self) -> usize {
+        self.members.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// returns true if a member occupies the slot
+    pub fn contains_member(&self, slot: usize) -> bool {
+        slot < self.capacity() && (self.members[slot / 64] >> (slot % 64)) & 1 == 1
+    }
+
+    /// number of bytes used by the bit slices
+    pub fn memory_size(&self) -> usize {
+        self.slices.len() * 8
+    }
+
+    /// returns true if the filter can be stored in the bank
+    pub fn accepts(&self, bloom: &Bloom1X) -> bool {
+        bloom.k() == self.k && bloom.l() == self.l && bloom.w() == self.w
+            && bloom.h() == self.row_bits + self.k * self.hash_bits
+            && bloom.index_mode() == self.index_mode && bloom.partitioned() == self.partitioned
+    }
+
+    /// adds a copy of the filter to the bank and returns the slot it was given
+    /// the lowest free slot is reused, the bank doubles its capacity when full
+    pub fn add(&mut self, bloom: &Bloom1X) -> usize {
+        assert!(self.accepts(bloom), "the filter geometry differs from the bank one");
+        let slot = match (0..self.capacity()).find(|&slot| !self.contains_member(slot)) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.capacity();
+                self.grow(self.words * 2);
+                slot
+            }
+        };
+        self.members[slot / 64] |= 1 << (slot % 64);
+        self.store(slot, Some(bloom));
+        slot
+    }
+
+    /// overwrites the member in the slot with a copy of the filter
+    pub fn replace(&mut self, slot: usize, bloom: &Bloom1X) {
+        assert!(self.contains_member(slot));
+        assert!(self.accepts(bloom), "the filter geometry differs from the bank one");
+        self.store(slot, Some(bloom));
+    }
+
+    /// removes the member in the slot, returns false if the slot was free
+    pub fn remove(&mut self, slot: usize) -> bool {
+        if !self.contains_member(slot) {
+            return false;
+        }
+        self.store(slot, None);
+        self.members[slot / 64] &= !(1 << (slot % 64));
+        true
+    }
+
+    /// rebuilds the member in the slot as a standalone filter
+    pub fn member(&self, slot: usize) -> Option<Bloom1X> {
+        if !self.contains_member(slot) {
+            return None;
+        }
+        let h = self.row_bits + self.k * self.hash_bits;
+        let mut bloom = Bloom1X::new(self.k, self.l, self.w, h).with_index_mode(self.index_mode);
+        if self.partitioned {
+            bloom = bloom.with_partitioned_rows();
+        }
+        let (word, mask) = (slot / 64, 1u64 << (slot % 64));
+        for i in 0..self.l {
+            let row = &mut bloom.filter[i];
+            for j in 0..self.w {
+                if self.slices[(i * self.w + j) * self.words + word] & mask != 0 {
+                    row[j / 8] |= 1 << (j % 8);
+                }
+            }
+        }
+        Some(bloom)
+    }
+
+    /// copies the bits of the filter into the slot, or clears the slot when there is no filter
+    fn store(&mut self, slot: usize, bloom: Option<&Bloom1X>) {
+        let (word, mask) = (slot / 64, 1u64 << (slot % 64));
+        for i in 0..self.l {
+            for j in 0..self.w {
+                let bit = bloom.is_some_and(|bloom| (bloom.filter[i][j / 8] >> (j % 8)) & 1 == 1);
+                let slice = &mut self.slices[(i * self.w + j) * self.words + word];
+                if bit {
+                    *slice |= mask;
+                } else {
+                    *slice &= !mask;
+                }
+            }
+        }
+    }
+
+    /// re-lays the slices out with more words per bit position
+    fn grow(&mut self, words: usize) {
+        let mut slices = vec![0u64; self.l * self.w * words].into_boxed_slice();
+        for position in 0..self.l * self.w {
+            let old = &self.slices[position * self.words..(position + 1) * self.words];
+            slices[position * words..position * words + self.words].copy_from_slice(old);
+        }
+        self.slices = slices;
+        self.members.resize(words, 0);
+        self.words = words;
+    }
+
+    /// row index and bit indexes of a key, the same ones its members use
+    fn locate(&self, digest: &KeyDigest) -> (usize, Vec<usize>) {
+        let row_index = index::row_index(&digest.0, self.l, self.row_bits);
+        let sub_hashes = index::sub_hashes(&digest.0, self.row_bits);
+        let bit_indexes = (0..self.k)
+            .map(|i| index::row_position(sub_hashes, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned))
+            .collect();
+        (row_index, bit_indexes)
+    }
+
+    /// returns the members that contain the key behind the digest
+    /// bit s % 64 of word s / 64 is set when the member in slot s contains the key
+    pub fn query_digest(&self, digest: &KeyDigest) -> Vec<u64> {
+        let (row_index, bit_indexes) = self.locate(digest);
+        let mut result = self.members.clone();
+        for bit_index in bit_indexes {
+            let position = (row_index * self.w + bit_index) * self.words;
+            for (word, slice) in result.iter_mut().zip(&self.slices[position..position + self.words]) {
+                *word &= slice;
+            }
+        }
+        result
+    }
+
+    pub fn query_bytes(&self, bytes: &[u8]) -> Vec<u64> {
+        self.query_digest(&digest::hash_bytes(bytes))
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> Vec<u64> {
+        self.query_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> Vec<u64> {
+        self.query_digest(&digest::hash_u64(bytes))
+    }
+
+    /// inserts the key behind the digest into the member in the slot
+    pub fn insert_digest(&mut self, slot: usize, digest: &KeyDigest) {
+        assert!(self.contains_member(slot));
+        let (row_index, bit_indexes) = self.locate(digest);
+        for bit_index in bit_indexes {
+            self.slices[(row_index * self.w + bit_index) * self.words + slot / 64] |= 1 << (slot % 64);
+        }
+    }
+
+    pub fn insert_bytes(&mut self, slot: usize, bytes: &[u8]) {
+        self.insert_digest(slot, &digest::hash_bytes(bytes))
+    }
+
+    pub fn insert_u32(&mut self, slot: usize, bytes: u32) {
+        self.insert_digest(slot, &digest::hash_u32(bytes))
+    }
+
+    pub fn insert_u64(&mut self, slot: usize, bytes: u64) {
+        self.insert_digest(slot, &digest::hash_u64(bytes))
+    }
+}
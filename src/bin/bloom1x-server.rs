@@ -0,0 +1,646 @@
+//! bloom1x-server: hosts named Bloom1X and Bloom1Counter filters behind the Redis protocol (RESP)
+//! so that any Redis client can share them; keys are hashed as the raw bytes of the item
+//! filters are created by BF.RESERVE or on the first BF.ADD/BF.MADD with the server default geometry
+
+use bloom_1x::bloom::Bloom1X;
+use bloom_1x::bloom_counter::{Bloom1Counter, CounterWidth, RemoveError};
+use bloom_1x::digest::{self, KeyDigest};
+use bloom_1x::filter::{filter_kind, FilterKind};
+use bloom_1x::index;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+const USAGE: &str = "usage: bloom1x-server [--listen ADDR | --unix PATH] [--k K --l L --w W --h H]
+                      [--snapshot FILE [--snapshot-interval SECONDS]] [--memory-limit MIB]
+  --listen             TCP address to serve, 127.0.0.1:6380 by default
+  --unix               Unix socket path to serve instead of TCP
+  --k --l --w --h      geometry of the filters created implicitly by BF.ADD and BF.MADD (4, 65536, 64, 96)
+  --snapshot           file the filters are loaded from at start and saved to periodically and on SAVE
+  --snapshot-interval  seconds between snapshots, 60 by default, 0 saves only on SAVE
+  --memory-limit       MiB all filters together may use, 1024 by default; larger filters are refused
+
+commands:
+  BF.RESERVE key k l w h [COUNTING bits] [PARTITIONED]   creates a filter
+  BF.ADD key item / BF.MADD key item...                  1 when the item was not present before
+  BF.EXISTS key item / BF.MEXISTS key item...            1 when the item may be present
+  BF.DEL key item / BF.MDEL key item...                  counting filters only, 1 when removed
+                                                         (the item counts as deleted once it is no longer present)
+  BF.INFO key, DEL key..., KEYS, SAVE, PING, QUIT";
+
+/// magic starting a snapshot file
+const SNAPSHOT_MAGIC: &[u8; 4] = b"B1XD";
+/// longest line accepted for an inline command or a RESP header
+const MAX_LINE: u64 = 64 * 1024;
+/// most bytes of bulk strings accepted in one command, items are at most 48 bytes anyway
+const MAX_COMMAND_SIZE: usize = 64 * 1024 * 1024;
+
+enum Hosted {
+    Bits(Bloom1X),
+    Counting(Bloom1Counter)
+}
+
+struct Entry {
+    filter: Hosted,
+    /// number of items added through the server
+    inserted: u64
+}
+
+impl Entry {
+    fn to_bytes(&self) -> Vec<u8> {
+        match &self.filter {
+            Hosted::Bits(bloom) => bloom.to_bytes(),
+            Hosted::Counting(counter) => counter.to_bytes()
+        }
+    }
+
+    fn from_bytes(bytes: &[u8], inserted: u64) -> Result<Self, String> {
+        let filter = match filter_kind(bytes) {
+            Ok(FilterKind::Bloom1Counter) => Hosted::Counting(Bloom1Counter::from_bytes(bytes).map_err(|error| error.to_string())?),
+            _ => Hosted::Bits(Bloom1X::from_bytes(bytes).map_err(|error| error.to_string())?)
+        };
+        Ok(Entry { filter, inserted })
+    }
+
+    /// returns true when the item was not present before, only those count as inserted
+    fn insert(&mut self, digest: &KeyDigest) -> bool {
+        let found = match &mut self.filter {
+            Hosted::Bits(bloom) => bloom.insert_digest(digest),
+            Hosted::Counting(counter) => counter.insert_digest(digest).and_result
+        };
+        self.inserted += (found == 0) as u64;
+        found == 0
+    }
+
+    fn contains(&self, digest: &KeyDigest) -> bool {
+        match &self.filter {
+            Hosted::Bits(bloom) => bloom.contains_digest(digest) == 1,
+            Hosted::Counting(counter) => counter.contains_digest(digest) == 1
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        match &self.filter {
+            Hosted::Bits(bloom) => bloom.memory_size(),
+            Hosted::Counting(counter) => counter.memory_size()
+        }
+    }
+}
+
+struct State {
+    filters: RwLock<HashMap<Vec<u8>, Entry>>,
+    /// geometry of implicitly created filters
+    geometry: (usize, usize, usize, usize),
+    snapshot: Option<String>,
+    /// held while a snapshot is written, so that SAVE and the periodic snapshots never share the temporary file
+    saving: Mutex<()>,
+    /// bytes all filters together may use
+    memory_limit: usize
+}
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>)
+}
+
+impl Reply {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Reply::Simple(text) => write!(out, "+{text}\r\n"),
+            Reply::Error(text) => write!(out, "-{text}\r\n"),
+            Reply::Integer(value) => write!(out, ":{value}\r\n"),
+            Reply::Bulk(bytes) => {
+                write!(out, "${}\r\n", bytes.len())?;
+                out.write_all(bytes)?;
+                out.write_all(b"\r\n")
+            }
+            Reply::Array(items) => {
+                write!(out, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write(out))
+            }
+        }
+    }
+}
+
+fn error(text: &str) -> Reply {
+    Reply::Error(format!("ERR {text}"))
+}
+
+fn wrong_arity(command: &str) -> Reply {
+    error(&format!("wrong number of arguments for '{command}' command"))
+}
+
+/// reads a line of at most MAX_LINE bytes, returns 0 at end of stream
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+/// reads one command, either a RESP array of bulk strings or an inline command; None at end of stream
+fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let invalid = |text: &str| io::Error::new(io::ErrorKind::InvalidData, text.to_string());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if !line.is_empty() {
+            break;
+        }
+    }
+    let line = line.trim_end();
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(line.split_whitespace().map(|word| word.as_bytes().to_vec()).collect()));
+    };
+    let count: usize = count.parse().map_err(|_| invalid("invalid multibulk length"))?;
+    if count > 1024 * 1024 {
+        return Err(invalid("invalid multibulk length"));
+    }
+    let mut arguments = Vec::with_capacity(count.min(1024));
+    let mut size = 0;
+    for _ in 0..count {
+        let mut header = String::new();
+        read_line(reader, &mut header)?;
+        let length: usize = header.trim_end().strip_prefix('$').and_then(|length| length.parse().ok())
+            .ok_or_else(|| invalid("expected a bulk string"))?;
+        size += length;
+        if size > MAX_COMMAND_SIZE {
+            return Err(invalid("command too large"));
+        }
+        //the buffer grows with the bytes actually received, not with the announced length
+        let mut argument = vec![];
+        reader.take(length as u64 + 2).read_to_end(&mut argument)?;
+        if argument.len() != length + 2 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bulk string ends early"));
+        }
+        argument.truncate(length);
+        arguments.push(argument);
+    }
+    Ok(Some(arguments))
+}
+
+fn item_digest(item: &[u8]) -> Result<KeyDigest, Reply> {
+    if item.len() > 48 {
+        return Err(error("items are limited to 48 bytes"));
+    }
+    Ok(digest::hash_bytes(item))
+}
+
+fn number(argument: &[u8]) -> Result<usize, Reply> {
+    std::str::from_utf8(argument).ok().and_then(|text| text.parse().ok()).ok_or_else(|| error("value is not an integer or out of range"))
+}
+
+/// bytes used by a filter of l rows of w counters of the given bits, None when it does not even fit a usize
+fn filter_size(l: usize, w: usize, bits: usize) -> Option<usize> {
+    Some(l.checked_mul(w)?.checked_mul(bits)? / 8)
+}
+
+/// checks that a new filter fits in what the existing filters leave of the memory limit
+fn check_memory(state: &State, filters: &HashMap<Vec<u8>, Entry>, size: Option<usize>) -> Result<(), Reply> {
+    let used: usize = filters.values().map(Entry::memory_size).sum();
+    match size {
+        Some(size) if size <= state.memory_limit.saturating_sub(used) => Ok(()),
+        _ => Err(error("the filter exceeds the server memory limit"))
+    }
+}
+
+fn reserve(state: &State, arguments: &[Vec<u8>]) -> Result<Reply, Reply> {
+    if arguments.len() < 6 {
+        return Err(wrong_arity("bf.reserve"));
+    }
+    let (k, l, w, h) = (number(&arguments[2])?, number(&arguments[3])?, number(&arguments[4])?, number(&arguments[5])?);
+    if !index::geometry_is_valid(k, l, w, h) {
+        return Err(error("invalid filter geometry"));
+    }
+    let (mut width, mut partitioned) = (None, false);
+    let mut options = arguments[6..].iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"COUNTING" => {
+                let bits = number(options.next().ok_or_else(|| error("COUNTING needs the counter bits"))?)?;
+                width = Some(CounterWidth::from_bits(bits).ok_or_else(|| error("counter bits must be 2, 4, 8 or 16"))?);
+            }
+            b"PARTITIONED" if w.is_multiple_of(k) => partitioned = true,
+            b"PARTITIONED" => return Err(error("partitioned rows need w to be a multiple of k")),
+            _ => return Err(error("syntax error"))
+        }
+    }
+    let mut filters = state.filters.write().unwrap();
+    if filters.contains_key(&arguments[1]) {
+        return Err(error("item exists"));
+    }
+    //checked before anything is allocated, a filter too large for the server would abort it
+    check_memory(state, &filters, filter_size(l, w, width.map_or(1, CounterWidth::bits)))?;
+    let filter = match width {
+        Some(width) => {
+            let counter = Bloom1Counter::new(k, l, w, h).with_counter_width(width);
+            Hosted::Counting(if partitioned { counter.with_partitioned_rows() } else { counter })
+        }
+        None => {
+            let bloom = Bloom1X::new(k, l, w, h);
+            Hosted::Bits(if partitioned { bloom.with_partitioned_rows() } else { bloom })
+        }
+    };
+    filters.insert(arguments[1].clone(), Entry { filter, inserted: 0 });
+    Ok(Reply::Simple("OK"))
+}
+
+/// BF.ADD and BF.MADD, creating the filter with the default geometry when needed
+fn add(state: &State, key: &[u8], items: &[Vec<u8>], multi: bool) -> Result<Reply, Reply> {
+    let digests = items.iter().map(|item| item_digest(item)).collect::<Result<Vec<_>, _>>()?;
+    let mut filters = state.filters.write().unwrap();
+    let (k, l, w, h) = state.geometry;
+    if !filters.contains_key(key) {
+        check_memory(state, &filters, filter_size(l, w, 1))?;
+    }
+    let entry = filters.entry(key.to_vec()).or_insert_with(|| Entry { filter: Hosted::Bits(Bloom1X::new(k, l, w, h)), inserted: 0 });
+    let replies: Vec<_> = digests.iter().map(|digest| Reply::Integer(entry.insert(digest) as i64)).collect();
+    Ok(if multi { Reply::Array(replies) } else { replies.into_iter().next().unwrap() })
+}
+
+fn exists(state: &State, key: &[u8], items: &[Vec<u8>], multi: bool) -> Result<Reply, Reply> {
+    let digests = items.iter().map(|item| item_digest(item)).collect::<Result<Vec<_>, _>>()?;
+    let filters = state.filters.read().unwrap();
+    let entry = filters.get(key);
+    let replies: Vec<_> = digests.iter().map(|digest| Reply::Integer(entry.is_some_and(|entry| entry.contains(digest)) as i64)).collect();
+    Ok(if multi { Reply::Array(replies) } else { replies.into_iter().next().unwrap() })
+}
+
+fn delete(state: &State, key: &[u8], items: &[Vec<u8>], multi: bool) -> Result<Reply, Reply> {
+    let digests = items.iter().map(|item| item_digest(item)).collect::<Result<Vec<_>, _>>()?;
+    let mut filters = state.filters.write().unwrap();
+    let Some(entry) = filters.get_mut(key) else {
+        return Err(error("not found"));
+    };
+    let Hosted::Counting(counter) = &mut entry.filter else {
+        return Err(error("items can only be deleted from counting filters"));
+    };
+    let mut replies = vec![];
+    for digest in digests.iter() {
+        match counter.remove_digest(digest) {
+            Ok(_) => {
+                //like BF.ADD only counts items that were not present, only an item that is gone is no longer counted
+                if counter.contains_digest(digest) == 0 {
+                    entry.inserted = entry.inserted.saturating_sub(1);
+                }
+                replies.push(Reply::Integer(1));
+            }
+            Err(RemoveError::NotPresent) => replies.push(Reply::Integer(0)),
+            Err(RemoveError::NotInserted) => replies.push(error("the item was never inserted"))
+        }
+    }
+    Ok(if multi { Reply::Array(replies) } else { replies.into_iter().next().unwrap() })
+}
+
+fn info(state: &State, key: &[u8]) -> Result<Reply, Reply> {
+    let filters = state.filters.read().unwrap();
+    let entry = filters.get(key).ok_or_else(|| error("not found"))?;
+    //counting filters report the fill of their Bloom1X projection
+    let projected;
+    let (kind, bits, size) = match &entry.filter {
+        Hosted::Bits(bloom) => ("Bloom1X".to_string(), bloom, bloom.memory_size()),
+        Hosted::Counting(counter) => {
+            projected = counter.to_bloom1x();
+            (format!("Bloom1Counter {} bit counters", counter.counter_width().bits()), &projected, counter.memory_size())
+        }
+    };
+    let field = |name: &str, value: String| [Reply::Bulk(name.as_bytes().to_vec()), Reply::Bulk(value.into_bytes())];
+    let fields = [
+        field("Type", kind),
+        field("k", bits.k().to_string()),
+        field("l", bits.l().to_string()),
+        field("w", bits.w().to_string()),
+        field("h", bits.h().to_string()),
+        field("Size", size.to_string()),
+        field("Number of items inserted", entry.inserted.to_string()),
+        field("Bits set", bits.count_ones().to_string()),
+        field("Estimated false positive rate", format!("{:e}", bits.estimated_false_positive_rate()))
+    ];
+    Ok(Reply::Array(fields.into_iter().flatten().collect()))
+}
+
+/// writes every filter to the snapshot file through a temporary file, so a crash never leaves a partial snapshot
+fn save(state: &State) -> io::Result<()> {
+    let Some(path) = state.snapshot.as_ref() else {
+        return Err(io::Error::other("no snapshot file configured"));
+    };
+    let _saving = state.saving.lock().unwrap();
+    let mut buffer = SNAPSHOT_MAGIC.to_vec();
+    {
+        let filters = state.filters.read().unwrap();
+        buffer.extend_from_slice(&(filters.len() as u32).to_le_bytes());
+        for (name, entry) in filters.iter() {
+            let bytes = entry.to_bytes();
+            buffer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(name);
+            buffer.extend_from_slice(&entry.inserted.to_le_bytes());
+            buffer.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            buffer.extend_from_slice(&bytes);
+        }
+    }
+    let temporary = format!("{path}.tmp");
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(&buffer)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+    //the rename only survives a crash once the directory is synced
+    let parent = std::path::Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty());
+    std::fs::File::open(parent.unwrap_or(std::path::Path::new(".")))?.sync_all()
+}
+
+fn load(path: &str) -> Result<HashMap<Vec<u8>, Entry>, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(error) => return Err(error.to_string())
+    };
+    let mut rest = bytes.strip_prefix(SNAPSHOT_MAGIC).ok_or("not a snapshot file")?;
+    let mut take = |count: usize| -> Result<&[u8], String> {
+        if rest.len() < count {
+            return Err("truncated snapshot".to_string());
+        }
+        let (taken, left) = rest.split_at(count);
+        rest = left;
+        Ok(taken)
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut filters = HashMap::new();
+    for _ in 0..count {
+        let name_length = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let name = take(name_length)?.to_vec();
+        let inserted = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let length = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+        let entry = Entry::from_bytes(take(length)?, inserted).map_err(|error| format!("{}: {error}", String::from_utf8_lossy(&name)))?;
+        filters.insert(name, entry);
+    }
+    Ok(filters)
+}
+
+fn execute(state: &State, arguments: &[Vec<u8>]) -> Reply {
+    let command = String::from_utf8_lossy(&arguments[0]).to_ascii_uppercase();
+    let key = arguments.get(1).map(Vec::as_slice).unwrap_or_default();
+    let items = arguments.get(2..).unwrap_or_default();
+    let arity = |valid: bool| if valid { Ok(()) } else { Err(wrong_arity(&command.to_ascii_lowercase())) };
+    let result = match command.as_str() {
+        "PING" => Ok(Reply::Simple("PONG")),
+        "COMMAND" => Ok(Reply::Array(vec![])),
+        "BF.RESERVE" => reserve(state, arguments),
+        "BF.ADD" => arity(arguments.len() == 3).and_then(|_| add(state, key, items, false)),
+        "BF.MADD" => arity(arguments.len() >= 3).and_then(|_| add(state, key, items, true)),
+        "BF.EXISTS" => arity(arguments.len() == 3).and_then(|_| exists(state, key, items, false)),
+        "BF.MEXISTS" => arity(arguments.len() >= 3).and_then(|_| exists(state, key, items, true)),
+        "BF.DEL" => arity(arguments.len() == 3).and_then(|_| delete(state, key, items, false)),
+        "BF.MDEL" => arity(arguments.len() >= 3).and_then(|_| delete(state, key, items, true)),
+        "BF.INFO" => arity(arguments.len() == 2).and_then(|_| info(state, key)),
+        "DEL" => arity(arguments.len() >= 2).map(|_| {
+            let mut filters = state.filters.write().unwrap();
+            Reply::Integer(arguments[1..].iter().filter(|name| filters.remove(*name).is_some()).count() as i64)
+        }),
+        "KEYS" => Ok(Reply::Array(state.filters.read().unwrap().keys().map(|name| Reply::Bulk(name.clone())).collect())),
+        "SAVE" => save(state).map(|_| Reply::Simple("OK")).map_err(|failure| error(&failure.to_string())),
+        _ => Err(error(&format!("unknown command '{}'", String::from_utf8_lossy(&arguments[0]))))
+    };
+    result.unwrap_or_else(|reply| reply)
+}
+
+fn session(state: &State, reader: impl Read, writer: impl Write) -> io::Result<()> {
+    let (mut reader, mut writer) = (BufReader::new(reader), io::BufWriter::new(writer));
+    while let Some(arguments) = read_command(&mut reader)? {
+        if arguments.is_empty() {
+            continue;
+        }
+        if arguments[0].eq_ignore_ascii_case(b"QUIT") {
+            Reply::Simple("OK").write(&mut writer)?;
+            return writer.flush();
+        }
+        execute(state, &arguments).write(&mut writer)?;
+        //answer pipelined commands together
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+fn main() {
+    let mut arguments = std::env::args().skip(1);
+    let mut options = HashMap::new();
+    while let Some(name) = arguments.next() {
+        let (Some(option), Some(value)) = (name.strip_prefix("--"), arguments.next()) else {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        };
+        options.insert(option.to_string(), value);
+    }
+    let fail = |text: String| -> ! {
+        eprintln!("bloom1x-server: {text}");
+        std::process::exit(2);
+    };
+    if let Some(name) = options.keys().find(|name| !["listen", "unix", "k", "l", "w", "h", "snapshot", "snapshot-interval", "memory-limit"].contains(&name.as_str())) {
+        fail(format!("unknown option --{name}\n{USAGE}"));
+    }
+    let number = |name: &str, default: usize| match options.get(name) {
+        Some(value) => value.parse().unwrap_or_else(|_| fail(format!("--{name} expects a number"))),
+        None => default
+    };
+    let geometry = (number("k", 4), number("l", 65536), number("w", 64), number("h", 96));
+    if !index::geometry_is_valid(geometry.0, geometry.1, geometry.2, geometry.3) {
+        fail("invalid default geometry".to_string());
+    }
+    let memory_limit = number("memory-limit", 1024).checked_mul(1024 * 1024).unwrap_or_else(|| fail("--memory-limit is too large".to_string()));
+    if filter_size(geometry.1, geometry.2, 1).is_none_or(|size| size > memory_limit) {
+        fail("the default geometry exceeds the memory limit".to_string());
+    }
+    let snapshot = options.get("snapshot").cloned();
+    let filters = match snapshot.as_ref() {
+        Some(path) => load(path).unwrap_or_else(|error| fail(format!("{path}: {error}"))),
+        None => HashMap::new()
+    };
+    let state = Arc::new(State { filters: RwLock::new(filters), geometry, snapshot, saving: Mutex::new(()), memory_limit });
+
+    let interval = number("snapshot-interval", 60);
+    if state.snapshot.is_some() && interval > 0 {
+        let state = state.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(interval as u64));
+            if let Err(error) = save(&state) {
+                eprintln!("bloom1x-server: snapshot failed: {error}");
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = options.get("unix") {
+        use std::os::unix::fs::FileTypeExt;
+        //only a socket left by an earlier run is replaced, anything else at the path is kept
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                std::fs::remove_file(path).unwrap_or_else(|error| fail(format!("{path}: {error}")))
+            }
+            Ok(_) => fail(format!("{path}: exists and is not a socket")),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => fail(format!("{path}: {error}"))
+        }
+        let listener = std::os::unix::net::UnixListener::bind(path).unwrap_or_else(|error| fail(format!("{path}: {error}")));
+        for stream in listener.incoming().flatten() {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                if let Ok(reader) = stream.try_clone() {
+                    let _ = session(&state, reader, stream);
+                }
+            });
+        }
+        return;
+    }
+    let address = options.get("listen").map(String::as_str).unwrap_or("127.0.0.1:6380");
+    let listener = std::net::TcpListener::bind(address).unwrap_or_else(|error| fail(format!("{address}: {error}")));
+    for stream in listener.incoming().flatten() {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            if let Ok(reader) = stream.try_clone() {
+                let _ = session(&state, reader, stream);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_state(snapshot: Option<String>) -> State {
+        State { filters: RwLock::new(HashMap::new()), geometry: (4, 4096, 64, 96), snapshot, saving: Mutex::new(()), memory_limit: 1 << 20 }
+    }
+
+    /// runs an inline command and returns the RESP reply
+    fn run(state: &State, command: &str) -> String {
+        let arguments: Vec<Vec<u8>> = command.split_whitespace().map(|argument| argument.as_bytes().to_vec()).collect();
+        let mut out = vec![];
+        execute(state, &arguments).write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn read_commands() {
+        let mut input = io::Cursor::new(b"*2\r\n$6\r\nBF.ADD\r\n$0\r\n\r\n\r\nPING  now\r\n*1\r\n$4\r\nPING\r\n".to_vec());
+        assert!(read_command(&mut input).unwrap() == Some(vec![b"BF.ADD".to_vec(), vec![]]));
+        //blank lines are skipped and inline commands split on whitespace
+        assert!(read_command(&mut input).unwrap() == Some(vec![b"PING".to_vec(), b"now".to_vec()]));
+        assert!(read_command(&mut input).unwrap() == Some(vec![b"PING".to_vec()]));
+        assert!(read_command(&mut input).unwrap().is_none());
+
+        let invalid = |input: &[u8]| read_command(&mut io::Cursor::new(input.to_vec())).is_err();
+        assert!(invalid(b"*1\r\n$5\r\nPING\r\n"));
+        assert!(invalid(b"*1\r\n:4\r\nPING\r\n"));
+        assert!(invalid(b"*1\r\n$4\r\nPI"));
+        //announced sizes are checked before anything is allocated
+        assert!(invalid(format!("*2\r\n${}\r\n", MAX_COMMAND_SIZE + 1).as_bytes()));
+        assert!(invalid(format!("*2\r\n${0}\r\n{1}\r\n${0}\r\n", MAX_COMMAND_SIZE / 2 + 1, "x".repeat(MAX_COMMAND_SIZE / 2 + 1)).as_bytes()));
+        assert!(invalid(format!("*{}\r\n$1\r\n", usize::MAX).as_bytes()));
+        assert!(invalid(&vec![b'a'; MAX_LINE as usize + 1]));
+    }
+
+    #[test]
+    fn execute_commands() {
+        let state = new_state(None);
+        assert!(run(&state, "PING") == "+PONG\r\n");
+        assert!(run(&state, "BF.RESERVE counted 4 4096 64 96 COUNTING 4") == "+OK\r\n");
+        assert!(run(&state, "BF.RESERVE counted 4 4096 64 96").starts_with("-ERR item exists"));
+        assert!(run(&state, "BF.RESERVE odd 4 1024 64 96").starts_with("-ERR invalid filter geometry"));
+        assert!(run(&state, "BF.ADD counted alpha") == ":1\r\n");
+        assert!(run(&state, "BF.ADD counted alpha") == ":0\r\n");
+        assert!(run(&state, "BF.MADD counted beta gamma alpha") == "*3\r\n:1\r\n:1\r\n:0\r\n");
+        assert!(run(&state, "BF.EXISTS counted beta") == ":1\r\n");
+        assert!(run(&state, "BF.MEXISTS counted gamma delta") == "*2\r\n:1\r\n:0\r\n");
+        assert!(run(&state, "BF.EXISTS missing alpha") == ":0\r\n");
+        assert!(run(&state, "BF.ADD counted").starts_with("-ERR wrong number of arguments"));
+
+        //duplicates are not counted as inserted
+        let info = run(&state, "BF.INFO counted");
+        assert!(info.contains("Number of items inserted\r\n$1\r\n3\r\n"));
+        assert!(info.contains("Bloom1Counter 4 bit counters"));
+        assert!(run(&state, "BF.DEL counted beta") == ":1\r\n");
+        assert!(run(&state, "BF.EXISTS counted beta") == ":0\r\n");
+        assert!(run(&state, "BF.DEL counted beta") == ":0\r\n");
+        assert!(run(&state, "BF.INFO counted").contains("Number of items inserted\r\n$1\r\n2\r\n"));
+
+        //BF.ADD creates a plain filter with the default geometry, which cannot delete
+        assert!(run(&state, "BF.ADD plain alpha") == ":1\r\n");
+        assert!(run(&state, "BF.INFO plain").contains("$7\r\nBloom1X\r\n"));
+        assert!(run(&state, "BF.DEL plain alpha").starts_with("-ERR items can only be deleted"));
+        assert!(run(&state, "BF.INFO missing").starts_with("-ERR not found"));
+        assert!(run(&state, "DEL plain missing") == ":1\r\n");
+        assert!(run(&state, "SAVE").starts_with("-ERR no snapshot file configured"));
+        assert!(run(&state, "NOPE").starts_with("-ERR unknown command"));
+    }
+
+    #[test]
+    fn counting_duplicates() {
+        let state = new_state(None);
+        assert!(run(&state, "BF.RESERVE counted 4 4096 64 96 COUNTING 4") == "+OK\r\n");
+        assert!(run(&state, "BF.ADD counted x") == ":1\r\n");
+        assert!(run(&state, "BF.ADD counted x") == ":0\r\n");
+        //the duplicate raised the counters, x survives one delete and still counts as inserted
+        assert!(run(&state, "BF.DEL counted x") == ":1\r\n");
+        assert!(run(&state, "BF.EXISTS counted x") == ":1\r\n");
+        assert!(run(&state, "BF.INFO counted").contains("Number of items inserted\r\n$1\r\n1\r\n"));
+        assert!(run(&state, "BF.DEL counted x") == ":1\r\n");
+        assert!(run(&state, "BF.EXISTS counted x") == ":0\r\n");
+        assert!(run(&state, "BF.INFO counted").contains("Number of items inserted\r\n$1\r\n0\r\n"));
+    }
+
+    #[test]
+    fn memory_limit() {
+        let state = new_state(None);
+        assert!(filter_size(usize::MAX, 64, 1).is_none());
+        assert!(run(&state, "BF.RESERVE huge 5 2147483648 256 96").starts_with("-ERR the filter exceeds the server memory limit"));
+        //16 bit counters take sixteen times the bytes of the bits
+        assert!(run(&state, "BF.RESERVE counted 4 4096 256 96 COUNTING 16").starts_with("-ERR the filter exceeds"));
+        assert!(run(&state, "BF.RESERVE counted 4 4096 256 96") == "+OK\r\n");
+        //128 KiB used, the rest of the MiB holds 28 default filters of 32 KiB
+        for i in 0..28 {
+            assert!(run(&state, &format!("BF.ADD implicit{i} x")) == ":1\r\n");
+        }
+        assert!(run(&state, "BF.ADD implicit28 x").starts_with("-ERR the filter exceeds"));
+        assert!(run(&state, "BF.ADD implicit0 y") == ":1\r\n");
+        assert!(run(&state, "DEL counted") == ":1\r\n");
+        assert!(run(&state, "BF.ADD implicit28 x") == ":1\r\n");
+        assert!(state.filters.read().unwrap().len() == 29);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let dir = std::env::temp_dir().join(format!("bloom1x-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("filters.snapshot").to_str().unwrap().to_string();
+        let state = new_state(Some(path.clone()));
+        assert!(run(&state, "BF.RESERVE counted 4 4096 64 96 COUNTING 8 PARTITIONED") == "+OK\r\n");
+        assert!(run(&state, "BF.MADD counted alpha beta alpha") == "*3\r\n:1\r\n:1\r\n:0\r\n");
+        assert!(run(&state, "BF.MADD plain gamma delta") == "*2\r\n:1\r\n:1\r\n");
+        assert!(run(&state, "SAVE") == "+OK\r\n");
+        assert!(!std::path::Path::new(&format!("{path}.tmp")).exists());
+
+        let loaded = State { filters: RwLock::new(load(&path).unwrap()), ..new_state(None) };
+        for name in ["counted", "plain"] {
+            assert!(run(&loaded, &format!("BF.INFO {name}")) == run(&state, &format!("BF.INFO {name}")));
+        }
+        assert!(run(&loaded, "BF.MEXISTS counted alpha beta gamma") == "*3\r\n:1\r\n:1\r\n:0\r\n");
+        assert!(run(&loaded, "BF.MEXISTS plain gamma delta") == "*2\r\n:1\r\n:1\r\n");
+        assert!(run(&loaded, "BF.DEL counted alpha") == ":1\r\n");
+
+        //a missing snapshot is an empty server, a foreign file is refused
+        assert!(load(dir.join("missing").to_str().unwrap()).unwrap().is_empty());
+        std::fs::write(&path, b"B1X1").unwrap();
+        assert!(load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
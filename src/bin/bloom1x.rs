@@ -0,0 +1,541 @@
+//! bloom1x: builds, queries and inspects serialized filters from the command line
+
+use bloom_1x::bloom::Bloom1X;
+use bloom_1x::bloom_counter::{Bloom1Counter, CounterWidth};
+use bloom_1x::digest::{self, KeyDigest};
+use bloom_1x::filter::{filter_from_bytes, filter_kind, FilterKind, MembershipFilter};
+use bloom_1x::index::{self, IndexMode};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage:
+  bloom1x build --k K --l L --w W --h H [--counting] [--counter-bits 2|4|8|16]
+                [--index-mode modulo|multiply-shift] [--partitioned]
+                [--format FORMAT] [--record-size N] --output FILE [INPUT...]
+      inserts the keys of the inputs (stdin when none is given) into a new filter
+  bloom1x query FILTER [--format FORMAT] [--record-size N]
+      reads keys from stdin and prints the ones the filter contains
+  bloom1x stats FILTER
+      prints geometry, fill, estimated key count, false positive rate and row histogram
+  bloom1x merge --output FILE FILTER...
+      or-s Bloom1X filters of the same geometry into one
+  bloom1x diff FILTER FILTER
+      compares the bits of two filters, exits with 1 when they differ
+  bloom1x export FILTER [--format readmemh|readmemb|coe|mif] [--output FILE]
+      writes the rows as a memory initialization file, one row per word
+
+key formats: lines (default, one key of at most 48 bytes per line), hex (one hex encoded key per line),
+u64 (one decimal integer per line, hashed like the _u64 API), binary (records of --record-size bytes, default 8)
+counting filters are inspected, diffed and exported through their Bloom1X projection";
+
+/// command line split into positional arguments, options with a value and flags
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        const FLAGS: [&str; 2] = ["--counting", "--partitioned"];
+        let mut parsed = Args { positional: vec![], options: HashMap::new(), flags: vec![] };
+        while let Some(arg) = args.next() {
+            if FLAGS.contains(&arg.as_str()) {
+                parsed.flags.push(arg);
+            } else if arg.starts_with("--") && arg.len() > 2 {
+                let value = args.next().ok_or(format!("{arg} needs a value"))?;
+                parsed.options.insert(arg[2..].to_string(), value);
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn number(&self, name: &str) -> Result<Option<usize>, String> {
+        match self.option(name) {
+            Some(value) => value.parse().map(Some).map_err(|_| format!("--{name} expects a number, got {value}")),
+            None => Ok(None)
+        }
+    }
+
+    fn required_number(&self, name: &str) -> Result<usize, String> {
+        self.number(name)?.ok_or(format!("--{name} is required"))
+    }
+
+    /// rejects options the command does not know, so that typos do not pass silently
+    fn only(&self, known: &[&str]) -> Result<(), String> {
+        match self.options.keys().find(|name| !known.contains(&name.as_str())) {
+            Some(name) => Err(format!("unknown option --{name}")),
+            None => Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum KeyFormat {
+    Lines,
+    Hex,
+    U64,
+    Binary(usize)
+}
+
+impl KeyFormat {
+    fn from_args(args: &Args) -> Result<Self, String> {
+        let record_size = args.number("record-size")?.unwrap_or(8);
+        match args.option("format").unwrap_or("lines") {
+            "lines" => Ok(KeyFormat::Lines),
+            "hex" => Ok(KeyFormat::Hex),
+            "u64" => Ok(KeyFormat::U64),
+            "binary" if (1..=48).contains(&record_size) => Ok(KeyFormat::Binary(record_size)),
+            "binary" => Err("--record-size must be between 1 and 48".to_string()),
+            format => Err(format!("unknown key format {format}"))
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        let _ = write!(text, "{byte:02x}");
+        text
+    })
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn bytes_digest(bytes: &[u8], position: &str) -> Result<KeyDigest, String> {
+    if bytes.len() > 48 {
+        return Err(format!("{position}: keys are limited to 48 bytes"));
+    }
+    Ok(digest::hash_bytes(bytes))
+}
+
+/// hashes every key of the input, handing each digest to visit along with the key as printed back
+fn read_keys(input: impl Read, format: KeyFormat, mut visit: impl FnMut(KeyDigest, &str)) -> Result<(), String> {
+    let mut input = io::BufReader::new(input);
+    if let KeyFormat::Binary(record_size) = format {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes).map_err(|error| error.to_string())?;
+        if !bytes.len().is_multiple_of(record_size) {
+            return Err(format!("the input is not a whole number of {record_size} byte records"));
+        }
+        for record in bytes.chunks(record_size) {
+            visit(digest::hash_bytes(record), &hex(record));
+        }
+        return Ok(());
+    }
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let key = line.trim_end_matches('\r');
+        if key.is_empty() {
+            continue;
+        }
+        let position = format!("line {}", number + 1);
+        let digest = match format {
+            KeyFormat::Lines => bytes_digest(key.as_bytes(), &position)?,
+            KeyFormat::Hex => bytes_digest(&unhex(key.trim()).ok_or(format!("{position}: invalid hex key"))?, &position)?,
+            KeyFormat::U64 => digest::hash_u64(key.trim().parse().map_err(|_| format!("{position}: invalid u64 key"))?),
+            KeyFormat::Binary(_) => unreachable!()
+        };
+        visit(digest, key);
+    }
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| format!("{path}: {error}"))
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> Result<(), String> {
+    match path {
+        Some(path) => std::fs::write(path, bytes).map_err(|error| format!("{path}: {error}")),
+        //a reader closing the pipe early (e.g. head) is not an error
+        None => match io::stdout().write_all(bytes) {
+            Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error.to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+/// loads a filter as a Bloom1X, projecting counting filters, and names its type
+fn load_bits(path: &str) -> Result<(Bloom1X, Option<Bloom1Counter>), String> {
+    let bytes = read_file(path)?;
+    let loaded = match filter_kind(&bytes) {
+        Ok(FilterKind::Bloom1Counter) => Bloom1Counter::from_bytes(&bytes).map(|counter| (counter.to_bloom1x(), Some(counter))),
+        _ => Bloom1X::from_bytes(&bytes).map(|bloom| (bloom, None))
+    };
+    loaded.map_err(|error| format!("{path}: {error}"))
+}
+
+fn build(args: &Args) -> Result<(), String> {
+    args.only(&["k", "l", "w", "h", "counter-bits", "index-mode", "format", "record-size", "output"])?;
+    let (k, l, w, h) = (args.required_number("k")?, args.required_number("l")?, args.required_number("w")?, args.required_number("h")?);
+    let index_mode = match args.option("index-mode").unwrap_or("modulo") {
+        "modulo" => IndexMode::Modulo,
+        "multiply-shift" => IndexMode::MultiplyShift,
+        mode => return Err(format!("unknown index mode {mode}"))
+    };
+    let format = KeyFormat::from_args(args)?;
+    let output = args.option("output").ok_or("--output is required")?;
+    if !index::geometry_is_valid(k, l, w, h) {
+        return Err("invalid geometry: 32 <= w <= 256 with w a multiple of 8, l >= 1024, k >= 2, h > 64 and the hash bits left after the row index split into k sub-hashes".to_string());
+    }
+    if args.flag("--partitioned") && !w.is_multiple_of(k) {
+        return Err("partitioned rows need w to be a multiple of k".to_string());
+    }
+    if args.option("counter-bits").is_some() && !args.flag("--counting") {
+        return Err("--counter-bits needs --counting".to_string());
+    }
+    let mut filter: Box<dyn MembershipFilter> = if args.flag("--counting") {
+        let bits = args.number("counter-bits")?.unwrap_or(8);
+        let width = CounterWidth::from_bits(bits).ok_or("--counter-bits must be 2, 4, 8 or 16")?;
+        let mut counter = Bloom1Counter::new(k, l, w, h).with_index_mode(index_mode).with_counter_width(width);
+        if args.flag("--partitioned") {
+            counter = counter.with_partitioned_rows();
+        }
+        Box::new(counter)
+    } else {
+        let mut bloom = Bloom1X::new(k, l, w, h).with_index_mode(index_mode);
+        if args.flag("--partitioned") {
+            bloom = bloom.with_partitioned_rows();
+        }
+        Box::new(bloom)
+    };
+
+    let mut keys = 0usize;
+    let mut insert = |digest: KeyDigest, _: &str| {
+        filter.insert_digest(&digest);
+        keys += 1;
+    };
+    if args.positional.is_empty() {
+        read_keys(io::stdin().lock(), format, &mut insert)?;
+    }
+    for path in args.positional.iter() {
+        let file = std::fs::File::open(path).map_err(|error| format!("{path}: {error}"))?;
+        read_keys(file, format, &mut insert).map_err(|error| format!("{path}: {error}"))?;
+    }
+    write_output(Some(output), &filter.to_bytes())?;
+    eprintln!("inserted {keys} keys into {output}");
+    Ok(())
+}
+
+fn query(args: &Args) -> Result<(), String> {
+    args.only(&["format", "record-size"])?;
+    let [path] = args.positional.as_slice() else {
+        return Err("query takes one filter".to_string());
+    };
+    let filter = filter_from_bytes(&read_file(path)?).map_err(|error| format!("{path}: {error}"))?;
+    let format = KeyFormat::from_args(args)?;
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let (mut keys, mut hits) = (0usize, 0usize);
+    let mut failure = Ok(());
+    read_keys(io::stdin().lock(), format, |digest, key| {
+        keys += 1;
+        if filter.contains_digest(&digest) == 1 {
+            hits += 1;
+            if failure.is_ok() {
+                failure = writeln!(stdout, "{key}");
+            }
+        }
+    })?;
+    failure.and_then(|_| stdout.flush()).map_err(|error| error.to_string())?;
+    eprintln!("{hits} of {keys} keys found");
+    Ok(())
+}
+
+fn stats(args: &Args) -> Result<(), String> {
+    args.only(&[])?;
+    let [path] = args.positional.as_slice() else {
+        return Err("stats takes one filter".to_string());
+    };
+    let (bloom, counter) = load_bits(path)?;
+    let mut histogram = vec![0usize; bloom.w() + 1];
+    for i in 0..bloom.l() {
+        histogram[bloom.row(i).iter().map(|byte| byte.count_ones() as usize).sum::<usize>()] += 1;
+    }
+    let mut report = String::new();
+    match counter.as_ref() {
+        Some(counter) => {
+            let _ = writeln!(report, "type: Bloom1Counter, {} bit counters", counter.counter_width().bits());
+            let _ = writeln!(report, "memory: {} bytes", counter.memory_size());
+        }
+        None => {
+            let _ = writeln!(report, "type: Bloom1X");
+            let _ = writeln!(report, "memory: {} bytes", bloom.memory_size());
+        }
+    }
+    let ones = bloom.count_ones();
+    let _ = writeln!(report, "k = {}, l = {}, w = {}, h = {}", bloom.k(), bloom.l(), bloom.w(), bloom.h());
+    let _ = writeln!(report, "bits set: {} of {} (fill {:.6})", ones, bloom.l() * bloom.w(), ones as f64 / (bloom.l() * bloom.w()) as f64);
+    let _ = writeln!(report, "estimated keys: {:.0}", bloom.estimated_count());
+    let _ = writeln!(report, "estimated false positive rate: {:.3e}", bloom.estimated_false_positive_rate());
+    let _ = writeln!(report, "row histogram (bits set: rows):");
+    for (ones, &rows) in histogram.iter().enumerate().filter(|&(_, &rows)| rows > 0) {
+        let _ = writeln!(report, "  {ones:>3}: {rows}");
+    }
+    write_output(None, report.as_bytes())
+}
+
+fn merge(args: &Args) -> Result<(), String> {
+    args.only(&["output"])?;
+    let output = args.option("output").ok_or("--output is required")?;
+    let Some((first, rest)) = args.positional.split_first() else {
+        return Err("merge takes at least one filter".to_string());
+    };
+    let load = |path: &String| {
+        let bytes = read_file(path)?;
+        if filter_kind(&bytes) == Ok(FilterKind::Bloom1Counter) {
+            return Err(format!("{path}: counting filters cannot be merged"));
+        }
+        Bloom1X::from_bytes(&bytes).map_err(|error| format!("{path}: {error}"))
+    };
+    let mut merged = load(first)?;
+    for path in rest {
+        let bloom = load(path)?;
+        if !merged.is_compatible(&bloom) {
+            return Err(format!("{path}: the geometry differs from {first}"));
+        }
+        merged.union(&bloom);
+    }
+    write_output(Some(output), &merged.to_bytes())
+}
+
+/// geometry and indexing of a filter, as given to build
+fn describe(bloom: &Bloom1X) -> String {
+    let index_mode = match bloom.index_mode() {
+        IndexMode::Modulo => "modulo",
+        IndexMode::MultiplyShift => "multiply-shift"
+    };
+    format!("k = {}, l = {}, w = {}, h = {}, index mode {index_mode}{}", bloom.k(), bloom.l(), bloom.w(), bloom.h(),
+        if bloom.partitioned() { ", partitioned" } else { "" })
+}
+
+/// returns true when the filters differ
+fn diff(args: &Args) -> Result<bool, String> {
+    args.only(&[])?;
+    let [first, second] = args.positional.as_slice() else {
+        return Err("diff takes two filters".to_string());
+    };
+    let ((a, _), (b, _)) = (load_bits(first)?, load_bits(second)?);
+    if !a.is_compatible(&b) {
+        println!("geometries differ: {} against {}", describe(&a), describe(&b));
+        return Ok(true);
+    }
+    let (mut only_a, mut only_b, mut both, mut rows) = (0, 0, 0, 0);
+    for i in 0..a.l() {
+        let (row_a, row_b) = (a.row(i), b.row(i));
+        rows += (row_a != row_b) as usize;
+        for (x, y) in row_a.iter().zip(row_b.iter()) {
+            only_a += (x & !y).count_ones();
+            only_b += (!x & y).count_ones();
+            both += (x & y).count_ones();
+        }
+    }
+    println!("bits only in {first}: {only_a}");
+    println!("bits only in {second}: {only_b}");
+    println!("bits in both: {both}");
+    println!("rows that differ: {rows} of {}", a.l());
+    Ok(rows > 0)
+}
+
+fn export(args: &Args) -> Result<(), String> {
+    args.only(&["format", "output"])?;
+    let [path] = args.positional.as_slice() else {
+        return Err("export takes one filter".to_string());
+    };
+    let (bloom, _) = load_bits(path)?;
+    let text = memory_file(&bloom, args.option("format").unwrap_or("readmemh"))?;
+    write_output(args.option("output"), text.as_bytes())
+}
+
+/// writes the rows of a filter as a memory initialization file, one row per word
+fn memory_file(bloom: &Bloom1X, format: &str) -> Result<String, String> {
+    //row bit j is bit j of the memory word, so the word is written from the last byte down
+    let word = |i: usize, binary: bool| -> String {
+        bloom.row(i).iter().rev().map(|byte| if binary { format!("{byte:08b}") } else { format!("{byte:02x}") }).collect()
+    };
+    let mut text = String::new();
+    match format {
+        "readmemh" | "readmemb" => {
+            for i in 0..bloom.l() {
+                let _ = writeln!(text, "{}", word(i, format == "readmemb"));
+            }
+        }
+        "coe" => {
+            let _ = writeln!(text, "memory_initialization_radix=16;");
+            let _ = writeln!(text, "memory_initialization_vector=");
+            for i in 0..bloom.l() {
+                let _ = writeln!(text, "{}{}", word(i, false), if i + 1 == bloom.l() { ";" } else { "," });
+            }
+        }
+        "mif" => {
+            let _ = writeln!(text, "WIDTH={};\nDEPTH={};\n\nADDRESS_RADIX=UNS;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN", bloom.w(), bloom.l());
+            for i in 0..bloom.l() {
+                let _ = writeln!(text, "\t{} : {};", i, word(i, false));
+            }
+            let _ = writeln!(text, "END;");
+        }
+        format => return Err(format!("unknown export format {format}"))
+    }
+    Ok(text)
+}
+
+/// runs a command, returning true when diff found differences
+fn run(command: Option<&str>, args: &Args) -> Result<bool, String> {
+    match command {
+        Some("build") => build(args).map(|_| false),
+        Some("query") => query(args).map(|_| false),
+        Some("stats") => stats(args).map(|_| false),
+        Some("merge") => merge(args).map(|_| false),
+        Some("diff") => diff(args),
+        Some("export") => export(args).map(|_| false),
+        _ => Err(USAGE.to_string())
+    }
+}
+
+/// exit status of a command: 0 on success, 1 when diff found differences, 2 on errors
+fn status(result: &Result<bool, String>) -> u8 {
+    match result {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(_) => 2
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    if matches!(command.as_deref(), Some("help") | Some("--help")) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let result = Args::parse(args).and_then(|parsed| run(command.as_deref(), &parsed));
+    if let Err(error) = result.as_ref() {
+        eprintln!("bloom1x: {error}");
+    }
+    ExitCode::from(status(&result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(input: &[u8], format: KeyFormat) -> Result<Vec<(KeyDigest, String)>, String> {
+        let mut keys = vec![];
+        read_keys(input, format, |digest, key| keys.push((digest, key.to_string())))?;
+        Ok(keys)
+    }
+
+    fn args(args: &[&str]) -> Args {
+        Args::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn unhex_keys() {
+        assert!(unhex("00ff7A") == Some(vec![0x00, 0xff, 0x7a]));
+        assert!(unhex("") == Some(vec![]));
+        assert!(unhex("abc").is_none());
+        assert!(unhex("zz").is_none());
+        assert!(unhex("é1").is_none());
+        assert!(hex(&unhex("c0ffee").unwrap()) == "c0ffee");
+    }
+
+    #[test]
+    fn key_formats() {
+        //CRLF line ends are dropped and empty lines skipped
+        let lines = keys(b"alpha\r\n\r\nbeta\n\ngamma", KeyFormat::Lines).unwrap();
+        let names: Vec<&str> = lines.iter().map(|(_, key)| key.as_str()).collect();
+        assert!(names == ["alpha", "beta", "gamma"]);
+        assert!(lines[1].0 == digest::hash_bytes(b"beta"));
+        assert!(keys(&[b'x'; 49], KeyFormat::Lines).unwrap_err().starts_with("line 1"));
+
+        let hex_keys = keys(b"c0ffee\r\n  00ff \n", KeyFormat::Hex).unwrap();
+        assert!(hex_keys[0].0 == digest::hash_bytes(&[0xc0, 0xff, 0xee]) && hex_keys[1].0 == digest::hash_bytes(&[0x00, 0xff]));
+        assert!(keys(b"c0ffee\nxyz\n", KeyFormat::Hex).unwrap_err().starts_with("line 2"));
+
+        let numbers = keys(b"7\r\n18446744073709551615\n", KeyFormat::U64).unwrap();
+        assert!(numbers[0].0 == digest::hash_u64(7) && numbers[1].0 == digest::hash_u64(u64::MAX));
+        assert!(keys(b"-1\n", KeyFormat::U64).is_err());
+
+        //binary records are hashed as is, CR and LF bytes included
+        let records = keys(b"\r\n\0\x01abcd", KeyFormat::Binary(4)).unwrap();
+        assert!(records.len() == 2);
+        assert!(records[0].0 == digest::hash_bytes(b"\r\n\0\x01") && records[0].1 == "0d0a0001");
+        assert!(records[1].0 == digest::hash_bytes(b"abcd"));
+        assert!(keys(b"abcde", KeyFormat::Binary(4)).is_err());
+        assert!(KeyFormat::from_args(&args(&["--format", "binary", "--record-size", "49"])).is_err());
+    }
+
+    #[test]
+    fn export_bit_order() {
+        let mut bloom = Bloom1X::new(4, 4096, 64, 96);
+        for i in 0..100u64 {
+            bloom.query_and_set_u64(i);
+        }
+        let hex_words: Vec<String> = memory_file(&bloom, "readmemh").unwrap().lines().map(String::from).collect();
+        let binary_words: Vec<String> = memory_file(&bloom, "readmemb").unwrap().lines().map(String::from).collect();
+        assert!(hex_words.len() == 4096 && binary_words.len() == 4096);
+        for i in 0..bloom.l() {
+            //row bit j is bit j of the word, the least significant digit is written last
+            let (hex_word, binary_word) = (hex_words[i].as_bytes(), binary_words[i].as_bytes());
+            assert!(hex_word.len() == 16 && binary_word.len() == 64);
+            for j in 0..64 {
+                let set = (bloom.row(i)[j / 8] >> (j % 8)) & 1 == 1;
+                assert!((binary_word[63 - j] == b'1') == set);
+                let digit = u8::from_str_radix(std::str::from_utf8(&hex_word[15 - j / 4..16 - j / 4]).unwrap(), 16).unwrap();
+                assert!(((digit >> (j % 4)) & 1 == 1) == set);
+            }
+        }
+        let coe = memory_file(&bloom, "coe").unwrap();
+        assert!(coe.lines().nth(2) == Some(format!("{},", hex_words[0]).as_str()) && coe.trim_end().ends_with(';'));
+        let mif = memory_file(&bloom, "mif").unwrap();
+        assert!(mif.contains(&format!("\t4095 : {};", hex_words[4095])) && mif.starts_with("WIDTH=64;\nDEPTH=4096;"));
+        assert!(memory_file(&bloom, "verilog").is_err());
+    }
+
+    #[test]
+    fn counter_bits_need_counting() {
+        let built = run(Some("build"), &args(&["--k", "4", "--l", "4096", "--w", "64", "--h", "96", "--counter-bits", "4", "--output", "unused"]));
+        assert!(built == Err("--counter-bits needs --counting".to_string()));
+    }
+
+    #[test]
+    fn diff_exit_status() {
+        let dir = std::env::temp_dir().join(format!("bloom1x-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut bloom = Bloom1X::new(4, 4096, 64, 96);
+        std::fs::write(path("empty"), bloom.to_bytes()).unwrap();
+        bloom.query_and_set_u64(1);
+        std::fs::write(path("one"), bloom.to_bytes()).unwrap();
+        std::fs::write(path("other"), Bloom1X::new(4, 4096, 32, 96).to_bytes()).unwrap();
+
+        let same = run(Some("diff"), &args(&[&path("one"), &path("one")]));
+        assert!(same == Ok(false) && status(&same) == 0);
+        let differ = run(Some("diff"), &args(&[&path("empty"), &path("one")]));
+        assert!(differ == Ok(true) && status(&differ) == 1);
+        assert!(status(&run(Some("diff"), &args(&[&path("one"), &path("other")]))) == 1);
+        let partitioned = Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows();
+        assert!(describe(&partitioned) == "k = 4, l = 4096, w = 64, h = 96, index mode modulo, partitioned");
+        assert!(describe(&Bloom1X::new(4, 4096, 64, 96).with_index_mode(IndexMode::MultiplyShift)).ends_with("index mode multiply-shift"));
+        assert!(status(&run(Some("diff"), &args(&[&path("one"), &path("missing")]))) == 2);
+        assert!(status(&run(Some("diff"), &args(&[&path("one")]))) == 2);
+        assert!(status(&run(Some("frobnicate"), &args(&[]))) == 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
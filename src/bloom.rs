@@ -1,5 +1,11 @@
 
 use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::codec::{self, DecodeError, Reader};
+use crate::digest::KeyDigest;
+use crate::index::{self, IndexMode};
+
+/// magic starting the encoding of a Bloom1X
+const MAGIC: &[u8; 4] = b"B1XF";
 
 #[derive(Debug)]
 pub struct Bloom1X {
@@ -13,8 +19,26 @@ pub struct Bloom1X {
     row_bits: usize,
     /// number of bits to be extracted from hash for each sub-hash
     hash_bits: usize,
+    /// how sub-hashes are mapped onto positions inside a row
+    index_mode: IndexMode,
+    /// confines sub-hash i to segment i of the row
+    partitioned: bool,
     /// memory representation of filter
-    filter: Box<[Box<[u8]>]>
+    pub(crate) filter: Box<[Box<[u8]>]>,
+    /// insertion number at which every bit was last set, row after row
+    /// only present when the filter was built with recency hints
+    pub(crate) recency: Option<Box<[u32]>>,
+    /// number of insertions, used to stamp the recency hints
+    pub(crate) insertions: u32
+}
+
+/// new content of a single row of a filter, as produced by an incremental projection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowUpdate {
+    /// index of the row inside the filter
+    pub row_index: usize,
+    /// bits of the row, w/8 bytes with bit i found in byte i/8 at position i%8
+    pub bits: Box<[u8]>
 }
 
 /// contains the info of a query given a hash digest
@@ -28,20 +52,32 @@ pub struct QueryResult {
     pub and_result: u8
 }
 
+/// outcome of an insertion as seen by the row it was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsertResult {
+    /// membership of the key before the insertion
+    pub and_result: u8,
+    /// number of bits that went from 0 to 1, 0 for a duplicate or a false positive
+    pub bits_set: usize,
+    /// whether the row had no bit set before the insertion
+    pub row_was_empty: bool
+}
+
 impl Bloom1X {
     /// a filter represents a 2D array 
     /// w -> size of a row
-    /// l -> number of rows
+    /// l -> number of rows (a power of two reads the row index directly from the hash)
     /// h -> size of hash function output in bits
     pub fn new(k: usize, l: usize, w: usize, h:usize) -> Self {
         //some decent numbers should be provided
         assert!(w >= 32 && w % 8 == 0 && w <= 256);
-        assert!(l >= 1024 && (l & (l - 1)) == 0 && l < u32::MAX as usize);
+        assert!(l >= 1024 && l < u32::MAX as usize);
         assert!(k >= 2);
         assert!(h > 64);
 
         //number of bits needed to get a row inside the filter
-        let row_bits = l.checked_ilog2().unwrap() as usize;
+        let row_bits = index::row_bits(k, l, h);
+        assert!(row_bits <= 32, "non power of two row counts are limited to 2^24 rows");
         assert!(row_bits < h);
         assert!((h - row_bits) % k == 0);
         let hash_bits = (h - row_bits) / k;
@@ -52,7 +88,30 @@ impl Bloom1X {
             filter.push(row.into_boxed_slice());
         }
         let filter =filter.into_boxed_slice();
-        Self { k, l, w, row_bits, hash_bits, filter }
+        Self { k, l, w, row_bits, hash_bits, index_mode: IndexMode::default(), partitioned: false, filter, recency: None, insertions: 0 }
+    }
+
+    /// selects how sub-hashes are mapped onto positions inside a row
+    /// must be chosen before any key is inserted, the default is IndexMode::Modulo
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// splits every row into k segments of w/k positions, sub-hash i always indexing segment i
+    /// this matches hardware wiring one memory column per hash and keeps the k positions of a key distinct
+    /// must be chosen before any key is inserted, w must be a multiple of k
+    pub fn with_partitioned_rows(mut self) -> Self {
+        assert!(self.w.is_multiple_of(self.k));
+        self.partitioned = true;
+        self
+    }
+
+    /// remembers the insertion at which every bit was last set, at the cost of 32 bits per filter bit
+    /// these hints drive the RetouchPolicy::LeastRecentlySet policy
+    pub fn with_recency_hints(mut self) -> Self {
+        self.recency = Some(vec![0u32; self.l * self.w].into_boxed_slice());
+        self
     }
 
     pub fn clear(&mut self) {
@@ -61,6 +120,150 @@ impl Bloom1X {
                 self.filter[i][j] = 0;
             }
         }
+        if let Some(recency) = self.recency.as_mut() {
+            recency.fill(0);
+        }
+        self.insertions = 0;
+    }
+
+    /// number of hashes
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// number of rows
+    pub fn l(&self) -> usize {
+        self.l
+    }
+
+    /// number of bits per row
+    pub fn w(&self) -> usize {
+        self.w
+    }
+
+    /// number of hash bits used per key
+    pub fn h(&self) -> usize {
+        self.row_bits + self.k * self.hash_bits
+    }
+
+    /// how sub-hashes are turned into positions in a row
+    pub fn index_mode(&self) -> IndexMode {
+        self.index_mode
+    }
+
+    /// whether every sub-hash indexes its own segment of the row
+    pub fn partitioned(&self) -> bool {
+        self.partitioned
+    }
+
+    /// number of bytes used by the bits of the filter
+    pub fn memory_size(&self) -> usize {
+        self.l * self.w / 8
+    }
+
+    /// number of bits set in the filter
+    pub fn count_ones(&self) -> usize {
+        self.filter.iter().flat_map(|row| row.iter()).map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    /// bits of a row, bit j of the row is bit j % 8 of byte j / 8
+    pub fn row(&self, row_index: usize) -> &[u8] {
+        &self.filter[row_index]
+    }
+
+    /// returns true if both filters place every key on the same bits
+    pub fn is_compatible(&self, other: &Bloom1X) -> bool {
+        self.k == other.k && self.l == other.l && self.w == other.w && self.h() == other.h()
+            && self.index_mode == other.index_mode && self.partitioned == other.partitioned
+    }
+
+    /// adds every key of other to the filter by or-ing the rows, both filters must be compatible
+    pub fn union(&mut self, other: &Bloom1X) {
+        assert!(self.is_compatible(other), "filters with different geometries cannot be merged");
+        for (row, other_row) in self.filter.iter_mut().zip(other.filter.iter()) {
+            for (byte, other_byte) in row.iter_mut().zip(other_row.iter()) {
+                *byte |= other_byte;
+            }
+        }
+    }
+
+    /// number of inserted keys estimated from the fill of every row:
+    /// a row with x of its w bits set has taken ln(1 - x/w) / (k ln(1 - 1/w)) keys, full rows count as w - 1 bits set
+    pub fn estimated_count(&self) -> f64 {
+        let w = self.w as f64;
+        self.filter.iter().map(|row| {
+            let ones = row.iter().map(|byte| byte.count_ones() as f64).sum::<f64>().min(w - 1.0);
+            f64::ln(1.0 - ones / w) / (self.k as f64 * f64::ln(1.0 - 1.0 / w))
+        }).sum()
+    }
+
+    /// false positive rate of a key never inserted, averaged over the rows it may hash to
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let total: f64 = self.filter.iter().map(|row| {
+            let fill = row.iter().map(|byte| byte.count_ones() as f64).sum::<f64>() / self.w as f64;
+            f64::powf(fill, self.k as f64)
+        }).sum();
+        total / self.l as f64
+    }
+
+    /// appends the encoding of the filter: geometry followed by the rows
+    pub(crate) fn encode(&self, buffer: &mut Vec<u8>) {
+        codec::put_header(buffer, MAGIC);
+        codec::put_u32(buffer, self.k as u32);
+        codec::put_u32(buffer, self.l as u32);
+        codec::put_u32(buffer, self.w as u32);
+        codec::put_u32(buffer, self.h() as u32);
+        buffer.push(self.index_mode.to_u8());
+        buffer.push(self.partitioned as u8);
+        for row in self.filter.iter() {
+            buffer.extend_from_slice(row);
+        }
+    }
+
+    /// reads a filter written by encode
+    pub(crate) fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.header(MAGIC)?;
+        let k = reader.u32()? as usize;
+        let l = reader.u32()? as usize;
+        let w = reader.u32()? as usize;
+        let h = reader.u32()? as usize;
+        let index_mode = IndexMode::from_u8(reader.u8()?).ok_or(DecodeError::InvalidParameters)?;
+        let partitioned = reader.flag()?;
+        if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+            return Err(DecodeError::InvalidParameters);
+        }
+        //check the size before allocating the rows
+        if reader.remaining() < l * w / 8 {
+            return Err(DecodeError::Truncated);
+        }
+        let mut bloom = Self::new(k, l, w, h).with_index_mode(index_mode);
+        bloom.partitioned = partitioned;
+        for row in bloom.filter.iter_mut() {
+            row.copy_from_slice(reader.take(w / 8)?);
+        }
+        Ok(bloom)
+    }
+
+    /// serializes the filter into a portable byte buffer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(22 + self.memory_size());
+        self.encode(&mut buffer);
+        buffer
+    }
+
+    /// rebuilds a filter serialized by to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let bloom = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(bloom)
+    }
+
+    /// overwrites the rows listed in the updates, leaving all other rows untouched
+    pub fn apply_row_updates(&mut self, updates: &[RowUpdate]) {
+        for update in updates {
+            self.filter[update.row_index].copy_from_slice(&update.bits);
+        }
     }
 
     /// the query functions check if a provided key is member of the filter
@@ -127,19 +330,7 @@ impl Bloom1X {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
-
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
-
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
-
-        let mut bit_indexes= vec![];
-        for i in 0..self.k {
-            let bit_index = (digest >> (i * self.hash_bits)) as usize % self.w;
-            bit_indexes.push(bit_index); 
-        }
-        
-        QueryResult { bit_indexes, row_index, and_result:0 }
+        self.locate(&digest)
     }
 
     //given an array of bytes, search their position in the filter and return the row index and bit indexes for all sub-hashes
@@ -149,15 +340,19 @@ impl Bloom1X {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
-        
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
+        self.locate(&digest)
+    }
 
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
+    /// given a digest obtained from the hash function, outputs the row index and bit indexes
+    /// without reading the filter
+    #[inline(always)]
+    pub(crate) fn locate(&self, digest: &[u32; 3]) -> QueryResult {
+        let row_index = index::row_index(digest, self.l, self.row_bits);
+        let digest = index::sub_hashes(digest, self.row_bits);
 
         let mut bit_indexes= vec![];
         for i in 0..self.k {
-            let bit_index = (digest >> (i * self.hash_bits)) as usize % self.w;
+            let bit_index = index::row_position(digest, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned);
             bit_indexes.push(bit_index); 
         }
         
@@ -167,16 +362,14 @@ impl Bloom1X {
     /// given a digest obtained from the hash function, outputs the query info
     #[inline(always)]
     fn parse_hash(&self, digest: &[u32; 3]) -> QueryResult {
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
-
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
+        let row_index = index::row_index(digest, self.l, self.row_bits);
+        let digest = index::sub_hashes(digest, self.row_bits);
         let row = &self.filter[row_index];
 
         let mut bit_indexes= vec![];
         let mut and_result: u8 = 1;
         for i in 0..self.k {
-            let bit_index = (digest >> (i * self.hash_bits)) as usize % self.w;
+            let bit_index = index::row_position(digest, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned);
             let byte = row[bit_index/8];
             let bit = (byte >> (bit_index % 8)) & 0x1;
             bit_indexes.push(bit_index);
@@ -186,15 +379,59 @@ impl Bloom1X {
     }
 
     /// given a query result, update the filter
+    /// returns the number of bits that went from 0 to 1
     #[inline(always)]
-    pub fn update_filter(&mut self, qr: &QueryResult) {
+    pub fn update_filter(&mut self, qr: &QueryResult) -> usize {
         let row = &mut self.filter[qr.row_index];
+        let mut bits_set = 0;
         for i in 0..self.k {
             let bit_index = qr.bit_indexes[i];
             let mut byte = row[bit_index/8];
+            //sub-hashes landing on the same bit count once
+            bits_set += ((byte >> (bit_index % 8)) & 1 == 0) as usize;
             byte |= 1 << (bit_index % 8);
             row[bit_index/8] = byte;
         }
+        if let Some(recency) = self.recency.as_mut() {
+            self.insertions = self.insertions.wrapping_add(1);
+            for i in 0..self.k {
+                recency[qr.row_index * self.w + qr.bit_indexes[i]] = self.insertions;
+            }
+        }
+        bits_set
+    }
+
+    /// given a query result, update the filter and report the insertion as seen by its row
+    pub fn insert_detailed(&mut self, qr: &QueryResult) -> InsertResult {
+        let row_was_empty = self.filter[qr.row_index].iter().all(|&byte| byte == 0);
+        let bits_set = self.update_filter(qr);
+        InsertResult { and_result: qr.and_result, bits_set, row_was_empty }
+    }
+
+    /// inserts a key and reports how many of its bits were new and whether its row was empty
+    pub fn insert_detailed_bytes(&mut self, bytes: &[u8]) -> InsertResult {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.insert_detailed(&query_result)
+    }
+
+    pub fn insert_detailed_u32(&mut self, bytes: u32) -> InsertResult {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.insert_detailed(&query_result)
+    }
+
+    pub fn insert_detailed_u64(&mut self, bytes: u64) -> InsertResult {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.insert_detailed(&query_result)
     }
 
     /// this functions queris the memebership returning the true/false response
@@ -229,4 +466,16 @@ impl Bloom1X {
         self.update_filter(&query_result);
         result   
     }
+
+    /// returns 0x1 if the key behind a precomputed digest was found inside the filter and 0x0 otherwise
+    pub fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        self.parse_hash(&digest.0).and_result
+    }
+
+    /// inserts the key behind a precomputed digest, returning its membership before the insertion
+    pub fn insert_digest(&mut self, digest: &KeyDigest) -> u8 {
+        let query_result = self.parse_hash(&digest.0);
+        self.update_filter(&query_result);
+        query_result.and_result
+    }
 }
\ No newline at end of file
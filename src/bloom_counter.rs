@@ -1,5 +1,14 @@
 
 use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::aging::Aging;
+use crate::bloom::{Bloom1X, RowUpdate};
+use crate::codec::{self, DecodeError, Reader};
+use crate::digest::KeyDigest;
+use crate::index::{self, IndexMode};
+use std::collections::HashMap;
+
+/// magic starting the encoding of a Bloom1Counter
+const MAGIC: &[u8; 4] = b"B1XC";
 
 #[derive(Debug)]
 pub struct Bloom1Counter {
@@ -13,8 +22,35 @@ pub struct Bloom1Counter {
     row_bits: usize,
     /// number of bits to be extracted from hash for each sub-hash
     hash_bits: usize,
-    /// memory representation of filter
-    filter: Box<[Box<[u8]>]>
+    /// how sub-hashes are mapped onto positions inside a row
+    index_mode: IndexMode,
+    /// confines sub-hash i to segment i of the row
+    partitioned: bool,
+    /// number of bits of each counter
+    width: CounterWidth,
+    /// memory representation of filter, each row packs w counters of `width` bits
+    filter: Box<[Box<[u8]>]>,
+    /// exact counts of saturated counters, keyed by (row index, counter index)
+    /// only present when the filter was built with an overflow stash
+    overflow: Option<HashMap<(usize, usize), u64>>,
+    /// number of keys and sum of their fingerprints for every row
+    /// only present when the filter was built in strict mode
+    checksums: Option<Box<[RowChecksum]>>,
+    /// zero/non-zero pattern of every row at the last incremental projection
+    projected: Option<Box<[Box<[u8]>]>>,
+    /// only raise the counters of a key that are equal to its current minimum
+    conservative_update: bool,
+    /// next row to be aged by age_fraction
+    aging_cursor: usize
+}
+
+/// keys stored in a row of a strict Bloom1Counter
+#[derive(Debug, Clone, Copy, Default)]
+struct RowChecksum {
+    /// number of keys inserted in the row
+    keys: u32,
+    /// wrapping sum of the fingerprints of these keys
+    sum: u16
 }
 
 /// contains the info of a query given a hash digest
@@ -22,28 +58,131 @@ pub struct Bloom1Counter {
 pub struct CounterResult {
     /// gets the index where the counter is found inside a row
     pub(crate) counter_indexes: Vec<usize>,
-    /// gets the value of each counter
-    pub(crate) counters: Vec<u8>,
     /// gets the row index inside the filter
     pub(crate) row_index: usize,
+    /// gets the fingerprint of the key, checked against the row checksum in strict mode
+    pub(crate) fingerprint: u16,
     /// gets the cummulated result of all queried counters
     pub and_result: u8
 }
 
+/// reasons for which a key cannot be removed from a Bloom1Counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoveError {
+    /// at least one of the k counters of the key is 0
+    NotPresent,
+    /// all counters of the key are non-zero but the row checksum shows the key was never inserted
+    NotInserted
+}
+
+impl std::fmt::Display for RemoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoveError::NotPresent => write!(f, "key is not present in the filter"),
+            RemoveError::NotInserted => write!(f, "key was never inserted in the filter")
+        }
+    }
+}
+
+impl std::error::Error for RemoveError {}
+
+/// number of bits used by each counter of a Bloom1Counter
+/// counters are packed inside the bytes of a row, starting from the least significant bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CounterWidth {
+    Bits2,
+    Bits4,
+    #[default]
+    Bits8,
+    Bits16
+}
+
+impl CounterWidth {
+    pub fn bits(self) -> usize {
+        match self {
+            CounterWidth::Bits2 => 2,
+            CounterWidth::Bits4 => 4,
+            CounterWidth::Bits8 => 8,
+            CounterWidth::Bits16 => 16
+        }
+    }
+
+    /// width with the given number of bits per counter
+    pub fn from_bits(bits: usize) -> Option<Self> {
+        match bits {
+            2 => Some(CounterWidth::Bits2),
+            4 => Some(CounterWidth::Bits4),
+            8 => Some(CounterWidth::Bits8),
+            16 => Some(CounterWidth::Bits16),
+            _ => None
+        }
+    }
+
+    /// value at which a counter of this width saturates
+    pub fn max(self) -> u16 {
+        ((1u32 << self.bits()) - 1) as u16
+    }
+}
+
+/// reads the counter at `index` from a row packed with `bits` wide counters
+#[inline(always)]
+fn read_counter(row: &[u8], index: usize, bits: usize) -> u16 {
+    if bits == 16 {
+        return u16::from_le_bytes([row[2 * index], row[2 * index + 1]]);
+    }
+    let bit_index = index * bits;
+    ((row[bit_index / 8] >> (bit_index % 8)) as u16) & ((1u16 << bits) - 1)
+}
+
+/// exact value of a counter, looked up in the overflow stash when the counter is saturated
+#[inline(always)]
+fn exact_count(stash: Option<&HashMap<(usize, usize), u64>>, row_index: usize, counter_index: usize, counter: u16, max: u16) -> u64 {
+    if counter < max {
+        return counter as u64;
+    }
+    stash.and_then(|stash| stash.get(&(row_index, counter_index)).copied()).unwrap_or(max as u64)
+}
+
+/// writes the counter at `index` into a row packed with `bits` wide counters
+#[inline(always)]
+fn write_counter(row: &mut [u8], index: usize, bits: usize, value: u16) {
+    if bits == 16 {
+        row[2 * index..2 * index + 2].copy_from_slice(&value.to_le_bytes());
+        return;
+    }
+    let bit_index = index * bits;
+    let mask = (((1u16 << bits) - 1) as u8) << (bit_index % 8);
+    let byte = &mut row[bit_index / 8];
+    *byte = (*byte & !mask) | (((value as u8) << (bit_index % 8)) & mask);
+}
+
+/// reports the outcome of incrementing or decrementing the counters of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateResult {
+    /// membership result before the update
+    pub and_result: u8,
+    /// number of counters that are saturated after the update
+    /// without an overflow stash these counters are sticky and no longer follow the real count
+    pub saturated: usize,
+    /// number of counters that were already 0 and could not be decremented
+    pub underflowed: usize
+}
+
 impl Bloom1Counter {
     /// a filter represents a 2D array 
     /// w -> size of a row (number of counters/row)
-    /// l -> number of rows
+    /// l -> number of rows (a power of two reads the row index directly from the hash)
     /// h -> size of hash function output in bits
     pub fn new(k: usize, l: usize, w: usize, h:usize) -> Self {
         //some decent numbers should be provided
         assert!(w >= 32 && w % 8 == 0 && w <= 256);
-        assert!(l >= 1024 && (l & (l - 1)) == 0 && l < u32::MAX as usize);
+        assert!(l >= 1024 && l < u32::MAX as usize);
         assert!(k >= 2);
         assert!(h > 64);
 
         //number of bits needed to get a row inside the filter
-        let row_bits = l.checked_ilog2().unwrap() as usize;
+        let row_bits = index::row_bits(k, l, h);
+        assert!(row_bits <= 32, "non power of two row counts are limited to 2^24 rows");
         assert!(row_bits < h);
         assert!((h - row_bits) % k == 0);
         let hash_bits = (h - row_bits) / k;
@@ -54,14 +193,220 @@ impl Bloom1Counter {
             filter.push(row.into_boxed_slice());
         }
         let filter =filter.into_boxed_slice();
-        Self { k, l, w, row_bits, hash_bits, filter }
+        Self { k, l, w, row_bits, hash_bits, index_mode: IndexMode::default(), partitioned: false, width: CounterWidth::default(), filter, overflow: None, checksums: None, projected: None, conservative_update: false, aging_cursor: 0 }
+    }
+
+    /// selects how sub-hashes are mapped onto positions inside a row
+    /// must be chosen before any key is inserted, the default is IndexMode::Modulo
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// splits every row into k segments of w/k positions, sub-hash i always indexing segment i
+    /// this matches hardware wiring one memory column per hash and keeps the k positions of a key distinct
+    /// must be chosen before any key is inserted, w must be a multiple of k
+    pub fn with_partitioned_rows(mut self) -> Self {
+        assert!(self.w.is_multiple_of(self.k));
+        self.partitioned = true;
+        self
+    }
+
+    /// selects the number of bits of each counter, the default being CounterWidth::Bits8
+    /// must be chosen before any key is inserted as it reallocates the filter
+    pub fn with_counter_width(mut self, width: CounterWidth) -> Self {
+        let mut filter = vec![];
+        for _ in 0..self.l {
+            let row = vec![0u8; self.w * width.bits() / 8];
+            filter.push(row.into_boxed_slice());
+        }
+        self.filter = filter.into_boxed_slice();
+        self.width = width;
+        self
+    }
+
+    /// increments only the counters of a key that hold its current minimum,
+    /// which keeps frequency estimates closer to the real counts
+    /// removals are not exact anymore in this mode since a key may not have raised all of its counters
+    pub fn with_conservative_update(mut self) -> Self {
+        self.conservative_update = true;
+        self
+    }
+
+    /// keeps a key count and a fingerprint checksum for every row so that try_remove
+    /// can detect keys that were never inserted but whose counters are all non-zero
+    /// the checksum only tells which keys a row holds while it holds at most one of them:
+    /// a wrong removal is refused when the row is empty, when it holds a single key with another
+    /// 16 bit fingerprint (missed with probability 2^-16), or when the removal would drain the row
+    /// while other keys are still counted in it
+    /// a wrong removal in a row holding two or more keys is not detected; it leaves the checksum
+    /// off, so a later legitimate removal from that row may be refused with NotInserted
+    pub fn with_strict_mode(mut self) -> Self {
+        self.checksums = Some(vec![RowChecksum::default(); self.l].into_boxed_slice());
+        self
+    }
+
+    /// number of bytes used by the counters of the filter
+    pub fn memory_size(&self) -> usize {
+        self.l * self.w * self.width.bits() / 8
+    }
+
+    /// number of hashes
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// number of rows
+    pub fn l(&self) -> usize {
+        self.l
+    }
+
+    /// number of counters per row
+    pub fn w(&self) -> usize {
+        self.w
+    }
+
+    /// number of hash bits used per key
+    pub fn h(&self) -> usize {
+        self.row_bits + self.k * self.hash_bits
+    }
+
+    /// number of bits of each counter
+    pub fn counter_width(&self) -> CounterWidth {
+        self.width
+    }
+
+    /// writes the geometry and modes, the packed rows,
+    /// then the overflow stash and the row checksums when the filter keeps them
+    pub(crate) fn encode(&self, buffer: &mut Vec<u8>) {
+        codec::put_header(buffer, MAGIC);
+        codec::put_u32(buffer, self.k as u32);
+        codec::put_u32(buffer, self.l as u32);
+        codec::put_u32(buffer, self.w as u32);
+        codec::put_u32(buffer, self.h() as u32);
+        buffer.push(self.index_mode.to_u8());
+        buffer.push(self.partitioned as u8);
+        buffer.push(self.width.bits() as u8);
+        buffer.push(self.conservative_update as u8);
+        buffer.push(self.overflow.is_some() as u8);
+        buffer.push(self.checksums.is_some() as u8);
+        for row in self.filter.iter() {
+            buffer.extend_from_slice(row);
+        }
+        if let Some(stash) = self.overflow.as_ref() {
+            //sorted so that equal filters always encode to equal buffers
+            let mut entries: Vec<_> = stash.iter().collect();
+            entries.sort_unstable();
+            codec::put_u64(buffer, entries.len() as u64);
+            for (&(row_index, counter_index), &count) in entries {
+                codec::put_u32(buffer, row_index as u32);
+                codec::put_u32(buffer, counter_index as u32);
+                codec::put_u64(buffer, count);
+            }
+        }
+        if let Some(checksums) = self.checksums.as_ref() {
+            for checksum in checksums.iter() {
+                codec::put_u32(buffer, checksum.keys);
+                buffer.extend_from_slice(&checksum.sum.to_le_bytes());
+            }
+        }
+    }
+
+    /// reads a filter written by encode
+    pub(crate) fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.header(MAGIC)?;
+        let k = reader.u32()? as usize;
+        let l = reader.u32()? as usize;
+        let w = reader.u32()? as usize;
+        let h = reader.u32()? as usize;
+        let index_mode = IndexMode::from_u8(reader.u8()?).ok_or(DecodeError::InvalidParameters)?;
+        let partitioned = reader.flag()?;
+        let width = CounterWidth::from_bits(reader.u8()? as usize).ok_or(DecodeError::InvalidParameters)?;
+        let conservative_update = reader.flag()?;
+        let overflow = reader.flag()?;
+        let strict = reader.flag()?;
+        if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+            return Err(DecodeError::InvalidParameters);
+        }
+        //check the size before allocating the rows
+        let row_size = w * width.bits() / 8;
+        if reader.remaining() < l * row_size {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut bloom = Self::new(k, l, w, h).with_index_mode(index_mode).with_counter_width(width);
+        bloom.partitioned = partitioned;
+        bloom.conservative_update = conservative_update;
+        for row in bloom.filter.iter_mut() {
+            row.copy_from_slice(reader.take(row_size)?);
+        }
+        if overflow {
+            let mut stash = HashMap::new();
+            for _ in 0..reader.u64()? {
+                let row_index = reader.u32()? as usize;
+                let counter_index = reader.u32()? as usize;
+                if row_index >= l || counter_index >= w {
+                    return Err(DecodeError::InvalidParameters);
+                }
+                stash.insert((row_index, counter_index), reader.u64()?);
+            }
+            bloom.overflow = Some(stash);
+        }
+        if strict {
+            let mut checksums = vec![];
+            for _ in 0..l {
+                let keys = reader.u32()?;
+                let sum = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+                checksums.push(RowChecksum { keys, sum });
+            }
+            bloom.checksums = Some(checksums.into_boxed_slice());
+        }
+        Ok(bloom)
+    }
+
+    /// serializes the filter into a portable byte buffer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(32 + self.memory_size());
+        self.encode(&mut buffer);
+        buffer
+    }
+
+    /// rebuilds a filter serialized by to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let bloom = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(bloom)
+    }
+
+    /// raw value of a single counter
+    #[inline(always)]
+    pub(crate) fn counter(&self, row_index: usize, counter_index: usize) -> u16 {
+        read_counter(&self.filter[row_index], counter_index, self.width.bits())
+    }
+
+    /// overwrites a single counter, the value must fit the counter width
+    #[inline(always)]
+    pub(crate) fn set_counter(&mut self, row_index: usize, counter_index: usize, value: u16) {
+        write_counter(&mut self.filter[row_index], counter_index, self.width.bits(), value);
+    }
+
+    /// keeps the exact count of every saturated counter in a side table
+    /// so that saturated counters can be decremented again
+    pub fn with_overflow_stash(mut self) -> Self {
+        self.overflow = Some(HashMap::new());
+        self
     }
 
     pub fn clear(&mut self) {
         for i in 0..self.l {
-            for j in 0..self.w {
-                self.filter[i][j] = 0;
-            }
+            self.filter[i].fill(0);
+        }
+        if let Some(stash) = self.overflow.as_mut() {
+            stash.clear();
+        }
+        if let Some(checksums) = self.checksums.as_mut() {
+            checksums.fill(RowChecksum::default());
         }
     }
 
@@ -117,7 +462,7 @@ impl Bloom1Counter {
         let mut and_result: u8 = 1;
         for i in 0..self.k {
             let counter_index = qr.counter_indexes[i];
-            let counter = row[counter_index];
+            let counter = read_counter(row, counter_index, self.width.bits());
             if counter == 0 {
                 and_result = 0;
                 break;
@@ -132,32 +477,34 @@ impl Bloom1Counter {
         hasher.permute_nc();
         let digest = hasher.digest_nc();
 
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
-
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
+        let row_index = index::row_index(&digest, self.l, self.row_bits);
+        let fingerprint = index::fingerprint(&digest);
+        let digest = index::sub_hashes(&digest, self.row_bits);
 
         let mut counter_indexes= vec![];
         for i in 0..self.k {
-            let counter_index = (digest >> (i * self.hash_bits)) as usize % self.w;
+            let counter_index = index::row_position(digest, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned);
             counter_indexes.push(counter_index); 
         }
         
-        CounterResult { counter_indexes, row_index, and_result:0, counters:vec![]}
+        CounterResult { counter_indexes, row_index, and_result:0, fingerprint }
     }
 
-    pub(crate) fn get_counters_u32(&self, bytes: u32) -> CounterResult{
+    /// values of the k counters of a key
+    #[cfg(test)]
+    pub(crate) fn get_counters_u32(&self, bytes: u32) -> Vec<u16> {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
         hasher.permute_nc();
-        let digest = hasher.digest_nc();
-        self.parse_hash(&digest)
+        let qr = self.parse_hash(&hasher.digest_nc());
+        qr.counter_indexes.iter().map(|&counter_index| self.counter(qr.row_index, counter_index)).collect()
     }
 
-    pub(crate) fn get_counters_u64(&self, bytes: u64) -> CounterResult{
+    #[cfg(test)]
+    pub(crate) fn get_counters_u64(&self, bytes: u64) -> Vec<u16> {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
         hasher.permute_nc();
-        let digest = hasher.digest_nc();
-        self.parse_hash(&digest)
+        let qr = self.parse_hash(&hasher.digest_nc());
+        qr.counter_indexes.iter().map(|&counter_index| self.counter(qr.row_index, counter_index)).collect()
     }
 
     //given an array of bytes, search their position in the filter and return the row index and bit indexes for all sub-hashes
@@ -168,129 +515,413 @@ impl Bloom1Counter {
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
-
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
+        let row_index = index::row_index(&digest, self.l, self.row_bits);
+        let fingerprint = index::fingerprint(&digest);
+        let digest = index::sub_hashes(&digest, self.row_bits);
 
         let mut counter_indexes= vec![];
         for i in 0..self.k {
-            let bit_index = (digest >> (i * self.hash_bits)) as usize % self.w;
+            let bit_index = index::row_position(digest, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned);
             counter_indexes.push(bit_index); 
         }
         
-        CounterResult { counter_indexes, row_index, and_result:0, counters: vec![]}
+        CounterResult { counter_indexes, row_index, and_result:0, fingerprint }
     }
 
     /// given a digest obtained from the hash function, outputs the query info
     #[inline(always)]
-    fn parse_hash(&self, digest: &[u32; 3]) -> CounterResult {
-        let row_index = (digest[2] >> (32 - self.row_bits)) as usize; 
-        let high_bits = digest[2] << self.row_bits;
-
-        let digest:u128 = digest[0] as u128 | ((digest[1] as u128) << 32) | (high_bits as u128) << (64 - self.row_bits);
+    pub(crate) fn parse_hash(&self, digest: &[u32; 3]) -> CounterResult {
+        let row_index = index::row_index(digest, self.l, self.row_bits);
+        let fingerprint = index::fingerprint(digest);
+        let digest = index::sub_hashes(digest, self.row_bits);
         let row = &self.filter[row_index];
 
         let mut counter_indexes= vec![];
-        let mut counters= vec![];
         let mut and_result: u8 = 1;
         for i in 0..self.k {
-            let counter_index = (digest >> (i * self.hash_bits)) as usize % self.w;
-            let counter = row[counter_index];
+            let counter_index = index::row_position(digest, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned);
+            let counter = read_counter(row, counter_index, self.width.bits());
             counter_indexes.push(counter_index);
-            counters.push(counter);
             if counter == 0 {
                 and_result = 0;
             }
         }
-        CounterResult { counter_indexes, row_index, and_result, counters }
+        CounterResult { counter_indexes, row_index, and_result, fingerprint }
     }
 
-    /// given a query result, update the filter
+    /// given a query result, increment its counters
+    /// a counter that reaches the maximum value of its width saturates: without an overflow stash it stays there,
+    /// with a stash further increments are counted in the stash
+    /// with conservative update only the counters equal to the current minimum of the key are raised
     #[inline(always)]
-    pub fn inc_counters(&mut self, qr: &CounterResult) {
+    pub fn inc_counters(&mut self, qr: &CounterResult) -> UpdateResult {
+        let mut update = UpdateResult { and_result: qr.and_result, saturated: 0, underflowed: 0 };
+        if let Some(checksums) = self.checksums.as_mut() {
+            let checksum = &mut checksums[qr.row_index];
+            checksum.keys += 1;
+            checksum.sum = checksum.sum.wrapping_add(qr.fingerprint);
+        }
+        let (bits, max) = (self.width.bits(), self.width.max());
+        let minimum = if self.conservative_update { Some(self.min_count(qr)) } else { None };
         let row = &mut self.filter[qr.row_index];
         for i in 0..self.k {
             let counter_index = qr.counter_indexes[i];
-            let counter = row[counter_index];
-            row[counter_index] = counter.wrapping_add(1);
+            let counter = read_counter(row, counter_index, bits);
+            if let Some(minimum) = minimum {
+                //a counter shared by several sub-hashes is raised only once as it leaves the minimum
+                if exact_count(self.overflow.as_ref(), qr.row_index, counter_index, counter, max) != minimum {
+                    if counter == max {
+                        update.saturated += 1;
+                    }
+                    continue;
+                }
+            }
+            if counter < max {
+                write_counter(row, counter_index, bits, counter + 1);
+            } else if let Some(stash) = self.overflow.as_mut() {
+                *stash.entry((qr.row_index, counter_index)).or_insert(max as u64) += 1;
+            }
+            if counter >= max - 1 {
+                update.saturated += 1;
+            }
         }
+        update
     }
 
-     /// given a query result, update the filter
+    /// given a query result, decrement its counters
+    /// counters at 0 are left untouched and reported as underflows,
+    /// saturated counters are only decremented when an overflow stash knows their exact count
     #[inline(always)]
-    pub fn dec_counters(&mut self, qr: &CounterResult) {
+    pub fn dec_counters(&mut self, qr: &CounterResult) -> UpdateResult {
+        let mut update = UpdateResult { and_result: qr.and_result, saturated: 0, underflowed: 0 };
+        if let Some(checksums) = self.checksums.as_mut() {
+            let checksum = &mut checksums[qr.row_index];
+            checksum.keys = checksum.keys.saturating_sub(1);
+            checksum.sum = checksum.sum.wrapping_sub(qr.fingerprint);
+        }
+        let (bits, max) = (self.width.bits(), self.width.max());
         let row = &mut self.filter[qr.row_index];
         for i in 0..self.k {
             let counter_index = qr.counter_indexes[i];
-            let counter = row[counter_index];
-            row[counter_index] = counter.wrapping_sub(1);
+            let counter = read_counter(row, counter_index, bits);
+            if counter == 0 {
+                update.underflowed += 1;
+                continue;
+            }
+            if counter == max {
+                let Some(stash) = self.overflow.as_mut() else {
+                    update.saturated += 1;
+                    continue;
+                };
+                if let Some(count) = stash.get_mut(&(qr.row_index, counter_index)) {
+                    *count -= 1;
+                    if *count == max as u64 {
+                        stash.remove(&(qr.row_index, counter_index));
+                    }
+                    update.saturated += 1;
+                    continue;
+                }
+            }
+            write_counter(row, counter_index, bits, counter - 1);
         }
+        update
     }
 
     /// these functions queries the memebership returning the true/false response
-    /// and also increments the counters of the filters
-    pub fn query_and_inc_bytes(&mut self, bytes: &[u8]) -> u8 {
+    /// and also increments the counters of the filters, reporting saturated counters
+    pub fn query_and_inc_bytes(&mut self, bytes: &[u8]) -> UpdateResult {
         assert!(bytes.len() <= 48); //limit to 48 bytes
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.inc_counters(&query_result);
-        result
+        self.inc_counters(&query_result)
     }
 
-    pub fn query_and_inc_u32(&mut self, bytes: u32) -> u8 {
+    pub fn query_and_inc_u32(&mut self, bytes: u32) -> UpdateResult {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.inc_counters(&query_result);
-        result    
+        self.inc_counters(&query_result)    
     }
 
-    pub fn query_and_inc_u64(&mut self, bytes: u64) -> u8 {
+    pub fn query_and_inc_u64(&mut self, bytes: u64) -> UpdateResult {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.inc_counters(&query_result);
-        result   
+        self.inc_counters(&query_result)   
+    }
+
+    /// returns 0x1 if the key behind a precomputed digest was found inside the filter and 0x0 otherwise
+    pub fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        self.parse_hash(&digest.0).and_result
+    }
+
+    /// increments the counters of the key behind a precomputed digest
+    pub fn insert_digest(&mut self, digest: &KeyDigest) -> UpdateResult {
+        let query_result = self.parse_hash(&digest.0);
+        self.inc_counters(&query_result)
+    }
+
+    /// removes the key behind a precomputed digest, see remove_by_result
+    pub fn remove_digest(&mut self, digest: &KeyDigest) -> Result<UpdateResult, RemoveError> {
+        let query_result = self.parse_hash(&digest.0);
+        self.remove_by_result(&query_result)
     }
 
     /// these functions queries the memebership returning the true/false response
-    /// and also decrements the counters of the filters
-    pub fn query_and_dec_bytes(&mut self, bytes: &[u8]) -> u8 {
+    /// and also decrements the counters of the filters, reporting saturated and underflowed counters
+    pub fn query_and_dec_bytes(&mut self, bytes: &[u8]) -> UpdateResult {
         assert!(bytes.len() <= 48); //limit to 48 bytes
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.dec_counters(&query_result);
-        result
+        self.dec_counters(&query_result)
     }
 
-    pub fn query_and_dec_u32(&mut self, bytes: u32) -> u8 {
+    pub fn query_and_dec_u32(&mut self, bytes: u32) -> UpdateResult {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.dec_counters(&query_result);
-        result    
+        self.dec_counters(&query_result)    
     }
 
-    pub fn query_and_dec_u64(&mut self, bytes: u64) -> u8 {
+    pub fn query_and_dec_u64(&mut self, bytes: u64) -> UpdateResult {
         let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
         hasher.permute_nc();
         let digest = hasher.digest_nc();
         let query_result = self.parse_hash(&digest);
-        let result = query_result.and_result;
-        self.dec_counters(&query_result);
-        result   
+        self.dec_counters(&query_result)   
+    }
+
+    /// given a query result, decrement its counters only if the key is currently present
+    /// in strict mode the row checksum must also agree with the removal, otherwise nothing is changed
+    pub fn remove_by_result(&mut self, qr: &CounterResult) -> Result<UpdateResult, RemoveError> {
+        if self.query_by_result(qr) == 0 {
+            return Err(RemoveError::NotPresent);
+        }
+        if let Some(checksums) = self.checksums.as_ref() {
+            let checksum = checksums[qr.row_index];
+            if checksum.keys == 0 || (checksum.keys == 1 && checksum.sum != qr.fingerprint) {
+                return Err(RemoveError::NotInserted);
+            }
+        }
+        let Some(checksum) = self.checksums.as_ref().map(|checksums| checksums[qr.row_index]) else {
+            return Ok(self.dec_counters(qr));
+        };
+        //the exact state touched by the removal, restored if the removal turns out to be wrong
+        let bits = self.width.bits();
+        let counters: Vec<(usize, u16)> = qr.counter_indexes.iter().map(|&counter_index| {
+            (counter_index, read_counter(&self.filter[qr.row_index], counter_index, bits))
+        }).collect();
+        let stashed: Vec<(usize, Option<u64>)> = match self.overflow.as_ref() {
+            Some(stash) => qr.counter_indexes.iter().map(|&counter_index| {
+                (counter_index, stash.get(&(qr.row_index, counter_index)).copied())
+            }).collect(),
+            None => vec![]
+        };
+
+        let update = self.dec_counters(qr);
+        //a drained row cannot still hold keys
+        let drained = self.filter[qr.row_index].iter().all(|&byte| byte == 0);
+        if drained && checksum.keys > 1 {
+            for (counter_index, counter) in counters {
+                write_counter(&mut self.filter[qr.row_index], counter_index, bits, counter);
+            }
+            if let Some(stash) = self.overflow.as_mut() {
+                for (counter_index, count) in stashed {
+                    match count {
+                        Some(count) => stash.insert((qr.row_index, counter_index), count),
+                        None => stash.remove(&(qr.row_index, counter_index))
+                    };
+                }
+            }
+            if let Some(checksums) = self.checksums.as_mut() {
+                checksums[qr.row_index] = checksum;
+            }
+            return Err(RemoveError::NotInserted);
+        }
+        Ok(update)
+    }
+
+    /// these functions remove a key only if all of its counters are non-zero
+    /// returns the updated counters or the reason for which the key was left untouched
+    pub fn try_remove_bytes(&mut self, bytes: &[u8]) -> Result<UpdateResult, RemoveError> {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.remove_by_result(&query_result)
+    }
+
+    pub fn try_remove_u32(&mut self, bytes: u32) -> Result<UpdateResult, RemoveError> {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.remove_by_result(&query_result)
+    }
+
+    pub fn try_remove_u64(&mut self, bytes: u64) -> Result<UpdateResult, RemoveError> {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.parse_hash(&digest);
+        self.remove_by_result(&query_result)
+    }
+
+    /// returns true if the key was present and has been removed
+    pub fn remove_bytes(&mut self, bytes: &[u8]) -> bool {
+        self.try_remove_bytes(bytes).is_ok()
+    }
+
+    pub fn remove_u32(&mut self, bytes: u32) -> bool {
+        self.try_remove_u32(bytes).is_ok()
+    }
+
+    pub fn remove_u64(&mut self, bytes: u64) -> bool {
+        self.try_remove_u64(bytes).is_ok()
+    }
+
+    /// bit pattern of a row, a bit being set exactly when its counter is non-zero
+    fn row_pattern(&self, row_index: usize) -> Box<[u8]> {
+        let bits = self.width.bits();
+        let row = &self.filter[row_index];
+        let mut pattern = vec![0u8; self.w / 8];
+        for counter_index in 0..self.w {
+            if read_counter(row, counter_index, bits) != 0 {
+                pattern[counter_index / 8] |= 1 << (counter_index % 8);
+            }
+        }
+        pattern.into_boxed_slice()
+    }
+
+    /// builds a Bloom1X with the same geometry that answers every query exactly like this filter
+    pub fn to_bloom1x(&self) -> Bloom1X {
+        let h = self.row_bits + self.k * self.hash_bits;
+        let mut bloom = Bloom1X::new(self.k, self.l, self.w, h).with_index_mode(self.index_mode);
+        if self.partitioned {
+            bloom = bloom.with_partitioned_rows();
+        }
+        for i in 0..self.l {
+            bloom.filter[i] = self.row_pattern(i);
+        }
+        bloom
+    }
+
+    /// returns the rows whose zero/non-zero pattern changed since the previous call
+    /// the first call compares against an empty filter, so applying every returned update
+    /// in order to an empty Bloom1X keeps it equal to to_bloom1x()
+    pub fn project_changes(&mut self) -> Vec<RowUpdate> {
+        let mut projected = self.projected.take().unwrap_or_else(|| {
+            vec![vec![0u8; self.w / 8].into_boxed_slice(); self.l].into_boxed_slice()
+        });
+        let mut updates = vec![];
+        for row_index in 0..self.l {
+            let bits = self.row_pattern(row_index);
+            if bits != projected[row_index] {
+                projected[row_index] = bits.clone();
+                updates.push(RowUpdate { row_index, bits });
+            }
+        }
+        self.projected = Some(projected);
+        updates
+    }
+
+    /// smallest exact value among the counters of a query result
+    pub(crate) fn min_count(&self, qr: &CounterResult) -> u64 {
+        let (bits, max) = (self.width.bits(), self.width.max());
+        let row = &self.filter[qr.row_index];
+        let mut minimum = u64::MAX;
+        for i in 0..self.k {
+            let counter_index = qr.counter_indexes[i];
+            let counter = read_counter(row, counter_index, bits);
+            minimum = minimum.min(exact_count(self.overflow.as_ref(), qr.row_index, counter_index, counter, max));
+        }
+        minimum
+    }
+
+    /// these functions estimate how many times a key was inserted, count-min style
+    /// the estimate never undercounts unless keys were removed, and is capped at the counter maximum
+    /// when saturated counters are not tracked by an overflow stash
+    pub fn estimate_count_bytes(&self, bytes: &[u8]) -> u64 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.min_count(&self.parse_hash(&digest))
+    }
+
+    pub fn estimate_count_u32(&self, bytes: u32) -> u64 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.min_count(&self.parse_hash(&digest))
+    }
+
+    pub fn estimate_count_u64(&self, bytes: u64) -> u64 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.min_count(&self.parse_hash(&digest))
+    }
+
+    /// returns 0x1 if the key was estimated to be inserted at least `threshold` times and 0x0 otherwise
+    pub fn query_at_least_bytes(&self, bytes: &[u8], threshold: u64) -> u8 {
+        (self.estimate_count_bytes(bytes) >= threshold) as u8
+    }
+
+    pub fn query_at_least_u32(&self, bytes: u32, threshold: u64) -> u8 {
+        (self.estimate_count_u32(bytes) >= threshold) as u8
+    }
+
+    pub fn query_at_least_u64(&self, bytes: u64, threshold: u64) -> u8 {
+        (self.estimate_count_u64(bytes) >= threshold) as u8
+    }
+
+    /// ages every counter of a row, keeping the overflow stash in sync
+    fn age_row(&mut self, row_index: usize, aging: Aging) {
+        let (bits, max) = (self.width.bits(), self.width.max());
+        let row = &mut self.filter[row_index];
+        for counter_index in 0..self.w {
+            let counter = read_counter(row, counter_index, bits);
+            if counter == 0 {
+                continue;
+            }
+            let aged = aging.apply(exact_count(self.overflow.as_ref(), row_index, counter_index, counter, max));
+            if let Some(stash) = self.overflow.as_mut() {
+                stash.remove(&(row_index, counter_index));
+                if aged > max as u64 {
+                    stash.insert((row_index, counter_index), aged);
+                }
+            }
+            write_counter(row, counter_index, bits, aged.min(max as u64) as u16);
+        }
+    }
+
+    /// ages all counters of the filter at once
+    /// saturated counters without an overflow stash are aged from the maximum value
+    pub fn age(&mut self, aging: Aging) {
+        assert!(self.checksums.is_none(), "aging is not supported in strict mode");
+        for row_index in 0..self.l {
+            self.age_row(row_index, aging);
+        }
+    }
+
+    /// ages the next ceil(fraction * l) rows, continuing where the previous call stopped
+    /// calling it 1/fraction times ages the whole filter once
+    pub fn age_fraction(&mut self, aging: Aging, fraction: f64) {
+        assert!(self.checksums.is_none(), "aging is not supported in strict mode");
+        assert!(fraction > 0.0 && fraction <= 1.0);
+        let rows = ((self.l as f64 * fraction).ceil() as usize).min(self.l);
+        for _ in 0..rows {
+            self.age_row(self.aging_cursor, aging);
+            self.aging_cursor = (self.aging_cursor + 1) % self.l;
+        }
     }
 }
\ No newline at end of file
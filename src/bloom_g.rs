@@ -0,0 +1,201 @@
+//! Bloom-g filter: the k bits of a key are spread over g rows instead of one
+//! every extra row costs one more memory access (e.g. the second port of a dual-port BRAM)
+//! but lowers the false positive rate towards the one of a standard Bloom filter
+//! with g = 1 the filter is bit for bit identical to a Bloom1X with the same parameters
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::index::{self, IndexMode};
+
+#[derive(Debug)]
+pub struct BloomG {
+    /// number of hashes
+    k: usize,
+    /// number of rows in filter
+    l: usize,
+    /// width of row in bits
+    w: usize,
+    /// number of rows accessed by each key
+    g: usize,
+    /// number of bits to be extracted from hash for computing each row index
+    row_bits: usize,
+    /// number of bits to be extracted from hash for each sub-hash
+    hash_bits: usize,
+    /// how sub-hashes are mapped onto positions inside a row
+    index_mode: IndexMode,
+    /// memory representation of filter
+    pub(crate) filter: Box<[Box<[u8]>]>
+}
+
+/// contains the info of a query given a hash digest
+#[derive(Debug, Clone)]
+pub struct BloomGResult {
+    /// gets the g row indexes accessed by the key
+    pub(crate) row_indexes: Vec<usize>,
+    /// gets the index where the bit is found inside its row, sub-hash i lives in row i % g
+    pub(crate) bit_indexes: Vec<usize>,
+    /// gets the bitwise and result between the bits
+    pub and_result: u8
+}
+
+impl BloomG {
+    /// a filter represents a 2D array
+    /// w -> size of a row
+    /// l -> number of rows (a power of two reads the row indexes directly from the hash)
+    /// h -> size of hash function output in bits
+    /// g -> number of rows accessed by each key, the k sub-hashes are dealt out over them
+    pub fn new(k: usize, l: usize, w: usize, h: usize, g: usize) -> Self {
+        //some decent numbers should be provided
+        assert!(w >= 32 && w.is_multiple_of(8) && w <= 256);
+        assert!(l >= 1024 && l < u32::MAX as usize);
+        assert!(k >= 2);
+        assert!(g >= 1 && g <= k);
+        assert!(h > 64 && h <= 96);
+
+        //number of bits needed to get each row inside the filter
+        let row_bits = index::row_bits_per_row(k, l, h, g);
+        assert!(row_bits <= 32, "non power of two row counts are limited to 2^24 rows");
+        assert!(g * row_bits < h);
+        assert!((h - g * row_bits).is_multiple_of(k));
+        let hash_bits = (h - g * row_bits) / k;
+
+        let mut filter = vec![];
+        for _ in 0..l {
+            let row = vec![0u8; w / 8];
+            filter.push(row.into_boxed_slice());
+        }
+        let filter = filter.into_boxed_slice();
+        Self { k, l, w, g, row_bits, hash_bits, index_mode: IndexMode::default(), filter }
+    }
+
+    /// selects how sub-hashes are mapped onto positions inside a row
+    /// must be chosen before any key is inserted, the default is IndexMode::Modulo
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// number of rows accessed by each key
+    pub fn g(&self) -> usize {
+        self.g
+    }
+
+    /// number of bytes used by the bits of the filter
+    pub fn memory_size(&self) -> usize {
+        self.l * self.w / 8
+    }
+
+    pub fn clear(&mut self) {
+        for row in self.filter.iter_mut() {
+            row.fill(0);
+        }
+    }
+
+    /// given a digest obtained from the hash function, outputs the rows and bits of the key
+    /// without reading the filter
+    #[inline(always)]
+    fn locate(&self, digest: &[u32; 3]) -> BloomGResult {
+        let mut row_indexes = vec![];
+        for j in 0..self.g {
+            row_indexes.push(index::nth_row_index(digest, self.l, self.row_bits, j));
+        }
+        let digest = index::sub_hashes_after_rows(digest, self.row_bits, self.g);
+
+        let mut bit_indexes = vec![];
+        for i in 0..self.k {
+            bit_indexes.push(index::sub_hash_index(digest, i, self.hash_bits, self.w, self.index_mode));
+        }
+        BloomGResult { row_indexes, bit_indexes, and_result: 0 }
+    }
+
+    /// given a digest obtained from the hash function, outputs the query info
+    #[inline(always)]
+    fn parse_hash(&self, digest: &[u32; 3]) -> BloomGResult {
+        let mut query_result = self.locate(digest);
+        query_result.and_result = self.query_by_result(&query_result);
+        query_result
+    }
+
+    // given an older query result, check the current and_result again
+    pub fn query_by_result(&self, qr: &BloomGResult) -> u8 {
+        let mut and_result: u8 = 1;
+        for i in 0..self.k {
+            let row = &self.filter[qr.row_indexes[i % self.g]];
+            let bit_index = qr.bit_indexes[i];
+            and_result &= (row[bit_index / 8] >> (bit_index % 8)) & 0x1;
+        }
+        and_result
+    }
+
+    /// given a query result, update the filter
+    #[inline(always)]
+    pub fn update_filter(&mut self, qr: &BloomGResult) {
+        for i in 0..self.k {
+            let row = &mut self.filter[qr.row_indexes[i % self.g]];
+            let bit_index = qr.bit_indexes[i];
+            row[bit_index / 8] |= 1 << (bit_index % 8);
+        }
+    }
+
+    //given a u64, search its position in the filter and return the row indexes and bit indexes for all sub-hashes
+    pub fn search_u64(&self, bytes: u64) -> BloomGResult {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        self.locate(&hasher.digest_nc())
+    }
+
+    //given an array of bytes, search their position in the filter and return the row indexes and bit indexes for all sub-hashes
+    pub fn search_bytes(&self, bytes: &[u8]) -> BloomGResult {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        self.locate(&hasher.digest_nc())
+    }
+
+    /// the query functions check if a provided key is member of the filter
+    /// returns 0x1 if the provided bytes were found inside the filter and 0x0 otherwise
+    pub fn query_bytes(&self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        self.parse_hash(&hasher.digest_nc()).and_result
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        self.parse_hash(&hasher.digest_nc()).and_result
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        self.parse_hash(&hasher.digest_nc()).and_result
+    }
+
+    /// this functions queris the memebership returning the true/false response
+    /// and also updates the filter with the new bits
+    pub fn query_and_set_bytes(&mut self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let query_result = self.parse_hash(&hasher.digest_nc());
+        self.update_filter(&query_result);
+        query_result.and_result
+    }
+
+    pub fn query_and_set_u32(&mut self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let query_result = self.parse_hash(&hasher.digest_nc());
+        self.update_filter(&query_result);
+        query_result.and_result
+    }
+
+    pub fn query_and_set_u64(&mut self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let query_result = self.parse_hash(&hasher.digest_nc());
+        self.update_filter(&query_result);
+        query_result.and_result
+    }
+}
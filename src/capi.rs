@@ -0,0 +1,394 @@
+//! C API over Bloom1X and Bloom1Counter, built with the `capi` feature
+//! filters are handed out as opaque pointers, every function reports a B1xStatus
+//! and results come back through out pointers; panics are caught before they reach C
+//! the header is generated into OUT_DIR, the committed include/bloom1x.h is refreshed by building with
+//! BLOOM1X_UPDATE_HEADER=1; the shared and static libraries come from the bloom1x-capi workspace member,
+//! so that Rust users of the crate do not pay for them: cargo build --release -p bloom1x-capi
+//!
+//! safety: filter pointers must come from the matching _new or _deserialize function and not be freed yet,
+//! other pointers must be valid for the length passed next to them (k entries for the search outputs)
+#![allow(clippy::missing_safety_doc)]
+
+use crate::bloom::Bloom1X;
+use crate::bloom_counter::{Bloom1Counter, CounterWidth, RemoveError};
+use crate::index::{self, IndexMode};
+use std::panic::{self, AssertUnwindSafe};
+
+/// status code returned by every function of the C API
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum B1xStatus {
+    Ok = 0,
+    /// a required pointer was null
+    NullPointer = 1,
+    /// invalid geometry, counter width, index mode or key longer than 48 bytes
+    InvalidArgument = 2,
+    /// the output buffer is too small, the required size was written to the size out pointer
+    BufferTooSmall = 3,
+    /// the serialized filter could not be decoded
+    DecodeError = 4,
+    /// removal of a key the filter does not contain
+    NotPresent = 5,
+    /// removal of a key the filter shows was never inserted
+    NotInserted = 6,
+    /// the library panicked, the filter should be considered corrupted
+    Panic = 7
+}
+
+/// runs a C API body, turning a panic into B1xStatus::Panic
+fn guard(body: impl FnOnce() -> B1xStatus) -> B1xStatus {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(B1xStatus::Panic)
+}
+
+fn index_mode(mode: u8) -> Option<IndexMode> {
+    IndexMode::from_u8(mode)
+}
+
+/// reads a key passed as pointer and length, null is accepted for an empty key
+unsafe fn key<'a>(key: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len > 48 || (key.is_null() && len != 0) {
+        return None;
+    }
+    if len == 0 {
+        return Some(&[]);
+    }
+    Some(unsafe { std::slice::from_raw_parts(key, len) })
+}
+
+/// copies a serialized filter into a caller buffer, reporting the needed size
+unsafe fn write_buffer(bytes: &[u8], buffer: *mut u8, capacity: usize, written: *mut usize) -> B1xStatus {
+    if written.is_null() {
+        return B1xStatus::NullPointer;
+    }
+    unsafe { *written = bytes.len() };
+    if buffer.is_null() || capacity < bytes.len() {
+        return B1xStatus::BufferTooSmall;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len()) };
+    B1xStatus::Ok
+}
+
+/// writes the row and bit indexes of a search, bit_indexes must hold k entries
+unsafe fn write_search(row: usize, bits: &[usize], row_index: *mut u32, bit_indexes: *mut u32, capacity: usize) -> B1xStatus {
+    if row_index.is_null() || bit_indexes.is_null() {
+        return B1xStatus::NullPointer;
+    }
+    if capacity < bits.len() {
+        return B1xStatus::BufferTooSmall;
+    }
+    unsafe {
+        *row_index = row as u32;
+        for (i, &bit) in bits.iter().enumerate() {
+            *bit_indexes.add(i) = bit as u32;
+        }
+    }
+    B1xStatus::Ok
+}
+
+/// creates a Bloom1X, index_mode is 0 for modulo and 1 for multiply-shift
+/// the filter must be released with b1x_filter_free
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_new(k: usize, l: usize, w: usize, h: usize, index_mode: u8, partitioned: bool, filter: *mut *mut Bloom1X) -> B1xStatus {
+    guard(|| {
+        if filter.is_null() {
+            return B1xStatus::NullPointer;
+        }
+        let Some(index_mode) = self::index_mode(index_mode) else {
+            return B1xStatus::InvalidArgument;
+        };
+        if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+            return B1xStatus::InvalidArgument;
+        }
+        let mut bloom = Bloom1X::new(k, l, w, h).with_index_mode(index_mode);
+        if partitioned {
+            bloom = bloom.with_partitioned_rows();
+        }
+        unsafe { *filter = Box::into_raw(Box::new(bloom)) };
+        B1xStatus::Ok
+    })
+}
+
+/// releases a filter, null is ignored
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_free(filter: *mut Bloom1X) {
+    if !filter.is_null() {
+        drop(unsafe { Box::from_raw(filter) });
+    }
+}
+
+/// inserts a key of at most 48 bytes, found is set to 1 if it was already present
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_insert_bytes(filter: *mut Bloom1X, key: *const u8, len: usize, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_mut() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        unsafe { *found = bloom.query_and_set_bytes(key) };
+        B1xStatus::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_insert_u64(filter: *mut Bloom1X, key: u64, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_mut() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { *found = bloom.query_and_set_u64(key) };
+        B1xStatus::Ok
+    })
+}
+
+/// found is set to 1 if the key of at most 48 bytes may be present and to 0 otherwise
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_query_bytes(filter: *const Bloom1X, key: *const u8, len: usize, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_ref() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        unsafe { *found = bloom.query_bytes(key) };
+        B1xStatus::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_query_u64(filter: *const Bloom1X, key: u64, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_ref() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { *found = bloom.query_u64(key) };
+        B1xStatus::Ok
+    })
+}
+
+/// writes the row of a key and the k bit indexes inside it, without reading the filter
+/// bit_indexes must hold at least k entries
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_search_bytes(filter: *const Bloom1X, key: *const u8, len: usize, row_index: *mut u32, bit_indexes: *mut u32, capacity: usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        let qr = bloom.search_bytes(key);
+        unsafe { write_search(qr.row_index, &qr.bit_indexes, row_index, bit_indexes, capacity) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_search_u64(filter: *const Bloom1X, key: u64, row_index: *mut u32, bit_indexes: *mut u32, capacity: usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        let qr = bloom.search_u64(key);
+        unsafe { write_search(qr.row_index, &qr.bit_indexes, row_index, bit_indexes, capacity) }
+    })
+}
+
+/// serializes the filter into buffer, written receives the size of the encoding
+/// a null buffer or a short capacity returns BufferTooSmall, which can be used to query the size
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_serialize(filter: *const Bloom1X, buffer: *mut u8, capacity: usize, written: *mut usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { write_buffer(&bloom.to_bytes(), buffer, capacity, written) }
+    })
+}
+
+/// rebuilds a filter serialized by b1x_filter_serialize
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_filter_deserialize(buffer: *const u8, len: usize, filter: *mut *mut Bloom1X) -> B1xStatus {
+    guard(|| {
+        if buffer.is_null() || filter.is_null() {
+            return B1xStatus::NullPointer;
+        }
+        match Bloom1X::from_bytes(unsafe { std::slice::from_raw_parts(buffer, len) }) {
+            Ok(bloom) => {
+                unsafe { *filter = Box::into_raw(Box::new(bloom)) };
+                B1xStatus::Ok
+            }
+            Err(_) => B1xStatus::DecodeError
+        }
+    })
+}
+
+/// creates a Bloom1Counter with counters of 2, 4, 8 or 16 bits
+/// the filter must be released with b1x_counter_free
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_new(k: usize, l: usize, w: usize, h: usize, counter_bits: u8, index_mode: u8, partitioned: bool, filter: *mut *mut Bloom1Counter) -> B1xStatus {
+    guard(|| {
+        if filter.is_null() {
+            return B1xStatus::NullPointer;
+        }
+        let (Some(index_mode), Some(width)) = (self::index_mode(index_mode), CounterWidth::from_bits(counter_bits as usize)) else {
+            return B1xStatus::InvalidArgument;
+        };
+        if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+            return B1xStatus::InvalidArgument;
+        }
+        let mut bloom = Bloom1Counter::new(k, l, w, h).with_index_mode(index_mode).with_counter_width(width);
+        if partitioned {
+            bloom = bloom.with_partitioned_rows();
+        }
+        unsafe { *filter = Box::into_raw(Box::new(bloom)) };
+        B1xStatus::Ok
+    })
+}
+
+/// releases a counting filter, null is ignored
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_free(filter: *mut Bloom1Counter) {
+    if !filter.is_null() {
+        drop(unsafe { Box::from_raw(filter) });
+    }
+}
+
+/// increments the counters of a key of at most 48 bytes, found is set to 1 if it was already present
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_insert_bytes(filter: *mut Bloom1Counter, key: *const u8, len: usize, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_mut() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        unsafe { *found = bloom.query_and_inc_bytes(key).and_result };
+        B1xStatus::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_insert_u64(filter: *mut Bloom1Counter, key: u64, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_mut() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { *found = bloom.query_and_inc_u64(key).and_result };
+        B1xStatus::Ok
+    })
+}
+
+fn remove_status(result: Result<crate::bloom_counter::UpdateResult, RemoveError>) -> B1xStatus {
+    match result {
+        Ok(_) => B1xStatus::Ok,
+        Err(RemoveError::NotPresent) => B1xStatus::NotPresent,
+        Err(RemoveError::NotInserted) => B1xStatus::NotInserted
+    }
+}
+
+/// decrements the counters of a key of at most 48 bytes inserted before
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_remove_bytes(filter: *mut Bloom1Counter, key: *const u8, len: usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_mut() }) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        remove_status(bloom.try_remove_bytes(key))
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_remove_u64(filter: *mut Bloom1Counter, key: u64) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_mut() }) else {
+            return B1xStatus::NullPointer;
+        };
+        remove_status(bloom.try_remove_u64(key))
+    })
+}
+
+/// found is set to 1 if the key of at most 48 bytes may be present and to 0 otherwise
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_query_bytes(filter: *const Bloom1Counter, key: *const u8, len: usize, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_ref() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        unsafe { *found = bloom.query_bytes(key) };
+        B1xStatus::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_query_u64(filter: *const Bloom1Counter, key: u64, found: *mut u8) -> B1xStatus {
+    guard(|| {
+        let (Some(bloom), false) = (unsafe { filter.as_ref() }, found.is_null()) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { *found = bloom.query_u64(key) };
+        B1xStatus::Ok
+    })
+}
+
+/// writes the row of a key and the k counter indexes inside it, counter_indexes must hold at least k entries
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_search_bytes(filter: *const Bloom1Counter, key: *const u8, len: usize, row_index: *mut u32, counter_indexes: *mut u32, capacity: usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        let Some(key) = (unsafe { self::key(key, len) }) else {
+            return B1xStatus::InvalidArgument;
+        };
+        let qr = bloom.search_bytes(key);
+        unsafe { write_search(qr.row_index, &qr.counter_indexes, row_index, counter_indexes, capacity) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_search_u64(filter: *const Bloom1Counter, key: u64, row_index: *mut u32, counter_indexes: *mut u32, capacity: usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        let qr = bloom.search_u64(key);
+        unsafe { write_search(qr.row_index, &qr.counter_indexes, row_index, counter_indexes, capacity) }
+    })
+}
+
+/// serializes the counting filter, see b1x_filter_serialize
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_serialize(filter: *const Bloom1Counter, buffer: *mut u8, capacity: usize, written: *mut usize) -> B1xStatus {
+    guard(|| {
+        let Some(bloom) = (unsafe { filter.as_ref() }) else {
+            return B1xStatus::NullPointer;
+        };
+        unsafe { write_buffer(&bloom.to_bytes(), buffer, capacity, written) }
+    })
+}
+
+/// rebuilds a counting filter serialized by b1x_counter_serialize
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn b1x_counter_deserialize(buffer: *const u8, len: usize, filter: *mut *mut Bloom1Counter) -> B1xStatus {
+    guard(|| {
+        if buffer.is_null() || filter.is_null() {
+            return B1xStatus::NullPointer;
+        }
+        match Bloom1Counter::from_bytes(unsafe { std::slice::from_raw_parts(buffer, len) }) {
+            Ok(bloom) => {
+                unsafe { *filter = Box::into_raw(Box::new(bloom)) };
+                B1xStatus::Ok
+            }
+            Err(_) => B1xStatus::DecodeError
+        }
+    })
+}
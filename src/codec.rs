@@ -0,0 +1,121 @@
+//! binary encoding shared by the serializable filters of the crate
+//! every encoding starts with a 4 byte magic and a version byte, all integers are little endian
+
+/// reasons for which a byte buffer cannot be decoded into a filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// the buffer ends before the encoded filter
+    Truncated,
+    /// the buffer does not start with the magic of the expected filter type
+    BadMagic,
+    /// the encoding version is not supported by this build
+    UnsupportedVersion(u8),
+    /// the encoded parameters do not describe a valid filter
+    InvalidParameters,
+    /// bytes are left after the encoded filter
+    TrailingBytes
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "buffer ends before the encoded filter"),
+            DecodeError::BadMagic => write!(f, "buffer does not hold the expected filter type"),
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported encoding version {}", version),
+            DecodeError::InvalidParameters => write!(f, "encoded filter parameters are invalid"),
+            DecodeError::TrailingBytes => write!(f, "bytes are left after the encoded filter")
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// version written by all encoders of the crate
+pub(crate) const VERSION: u8 = 1;
+
+/// sequential reader over an encoded buffer
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub(crate) fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.position.checked_add(count).ok_or(DecodeError::Truncated)?;
+        let slice = self.bytes.get(self.position..end).ok_or(DecodeError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    /// number of bytes not read yet
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    /// checks the magic and the version of an encoding
+    pub(crate) fn header(&mut self, magic: &[u8; 4]) -> Result<(), DecodeError> {
+        if self.take(4)? != magic {
+            return Err(DecodeError::BadMagic);
+        }
+        match self.u8()? {
+            VERSION => Ok(()),
+            version => Err(DecodeError::UnsupportedVersion(version))
+        }
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// reads a boolean stored as a 0 or 1 byte
+    pub(crate) fn flag(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidParameters)
+        }
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    /// fails if bytes are left after the decoded filter
+    pub(crate) fn finish(&self) -> Result<(), DecodeError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+/// writes the magic and the version of an encoding
+pub(crate) fn put_header(buffer: &mut Vec<u8>, magic: &[u8; 4]) {
+    buffer.extend_from_slice(magic);
+    buffer.push(VERSION);
+}
+
+pub(crate) fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_f64(buffer: &mut Vec<u8>, value: f64) {
+    put_u64(buffer, value.to_bits());
+}
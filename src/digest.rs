@@ -0,0 +1,35 @@
+//! hashing a key once so that its digest can probe any number of filters
+//! the digest does not depend on the filter geometry, each filter derives its own row and sub-hashes from it
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+
+/// Xoodoo digest of a key, accepted by every filter of the crate whatever its l and w
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyDigest(pub(crate) [u32; 3]);
+
+impl KeyDigest {
+    /// the three 32-bit words of the digest
+    pub fn words(&self) -> [u32; 3] {
+        self.0
+    }
+}
+
+/// hashes a key of at most 48 bytes
+pub fn hash_bytes(bytes: &[u8]) -> KeyDigest {
+    assert!(bytes.len() <= 48); //limit to 48 bytes
+    let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+    hasher.permute_nc();
+    KeyDigest(hasher.digest_nc())
+}
+
+pub fn hash_u32(bytes: u32) -> KeyDigest {
+    let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+    hasher.permute_nc();
+    KeyDigest(hasher.digest_nc())
+}
+
+pub fn hash_u64(bytes: u64) -> KeyDigest {
+    let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+    hasher.permute_nc();
+    KeyDigest(hasher.digest_nc())
+}
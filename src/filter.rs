@@ -0,0 +1,174 @@
+use crate::bloom::Bloom1X;
+use crate::bloom_counter::{Bloom1Counter, RemoveError};
+use crate::codec::DecodeError;
+use crate::digest::KeyDigest;
+
+/// geometry shared by every Bloom-1 style filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterParameters {
+    /// number of hashes
+    pub k: usize,
+    /// number of rows
+    pub l: usize,
+    /// number of bits or counters per row
+    pub w: usize,
+    /// number of hash bits used per key
+    pub h: usize,
+}
+
+/// common interface of the filters, object safe so it can be used as Box<dyn MembershipFilter>
+pub trait MembershipFilter {
+    /// inserts a key of at most 48 bytes and returns 0x1 if it was already present
+    fn insert_bytes(&mut self, bytes: &[u8]) -> u8;
+    /// inserts a u64 key and returns 0x1 if it was already present
+    fn insert_u64(&mut self, bytes: u64) -> u8;
+    /// returns 0x1 if a key of at most 48 bytes may be present
+    fn contains_bytes(&self, bytes: &[u8]) -> u8;
+    /// returns 0x1 if a u64 key may be present
+    fn contains_u64(&self, bytes: u64) -> u8;
+    /// inserts the key behind a precomputed digest and returns 0x1 if it was already present
+    fn insert_digest(&mut self, digest: &KeyDigest) -> u8;
+    /// returns 0x1 if the key behind a precomputed digest may be present
+    fn contains_digest(&self, digest: &KeyDigest) -> u8;
+    /// removes all keys
+    fn clear(&mut self);
+    /// geometry the filter was built with
+    fn parameters(&self) -> FilterParameters;
+    /// number of bytes used by the filter storage
+    fn memory_size(&self) -> usize;
+    /// serializes the filter, see filter_from_bytes for the reverse
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+/// filters that can also remove keys
+pub trait DeletableFilter: MembershipFilter {
+    /// removes a key of at most 48 bytes inserted before
+    fn remove_bytes(&mut self, bytes: &[u8]) -> Result<(), RemoveError>;
+    /// removes a u64 key inserted before
+    fn remove_u64(&mut self, bytes: u64) -> Result<(), RemoveError>;
+    /// removes the key behind a precomputed digest
+    fn remove_digest(&mut self, digest: &KeyDigest) -> Result<(), RemoveError>;
+}
+
+impl MembershipFilter for Bloom1X {
+    fn insert_bytes(&mut self, bytes: &[u8]) -> u8 {
+        self.query_and_set_bytes(bytes)
+    }
+
+    fn insert_u64(&mut self, bytes: u64) -> u8 {
+        self.query_and_set_u64(bytes)
+    }
+
+    fn contains_bytes(&self, bytes: &[u8]) -> u8 {
+        self.query_bytes(bytes)
+    }
+
+    fn contains_u64(&self, bytes: u64) -> u8 {
+        self.query_u64(bytes)
+    }
+
+    fn insert_digest(&mut self, digest: &KeyDigest) -> u8 {
+        Bloom1X::insert_digest(self, digest)
+    }
+
+    fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        Bloom1X::contains_digest(self, digest)
+    }
+
+    fn clear(&mut self) {
+        Bloom1X::clear(self)
+    }
+
+    fn parameters(&self) -> FilterParameters {
+        FilterParameters { k: self.k(), l: self.l(), w: self.w(), h: self.h() }
+    }
+
+    fn memory_size(&self) -> usize {
+        Bloom1X::memory_size(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        Bloom1X::to_bytes(self)
+    }
+}
+
+impl MembershipFilter for Bloom1Counter {
+    fn insert_bytes(&mut self, bytes: &[u8]) -> u8 {
+        self.query_and_inc_bytes(bytes).and_result
+    }
+
+    fn insert_u64(&mut self, bytes: u64) -> u8 {
+        self.query_and_inc_u64(bytes).and_result
+    }
+
+    fn contains_bytes(&self, bytes: &[u8]) -> u8 {
+        self.query_bytes(bytes)
+    }
+
+    fn contains_u64(&self, bytes: u64) -> u8 {
+        self.query_u64(bytes)
+    }
+
+    fn insert_digest(&mut self, digest: &KeyDigest) -> u8 {
+        Bloom1Counter::insert_digest(self, digest).and_result
+    }
+
+    fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        Bloom1Counter::contains_digest(self, digest)
+    }
+
+    fn clear(&mut self) {
+        Bloom1Counter::clear(self)
+    }
+
+    fn parameters(&self) -> FilterParameters {
+        FilterParameters { k: self.k(), l: self.l(), w: self.w(), h: self.h() }
+    }
+
+    fn memory_size(&self) -> usize {
+        Bloom1Counter::memory_size(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        Bloom1Counter::to_bytes(self)
+    }
+}
+
+impl DeletableFilter for Bloom1Counter {
+    fn remove_bytes(&mut self, bytes: &[u8]) -> Result<(), RemoveError> {
+        self.try_remove_bytes(bytes).map(|_| ())
+    }
+
+    fn remove_u64(&mut self, bytes: u64) -> Result<(), RemoveError> {
+        self.try_remove_u64(bytes).map(|_| ())
+    }
+
+    fn remove_digest(&mut self, digest: &KeyDigest) -> Result<(), RemoveError> {
+        Bloom1Counter::remove_digest(self, digest).map(|_| ())
+    }
+}
+
+/// types of filter that MembershipFilter::to_bytes can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Bloom1X,
+    Bloom1Counter
+}
+
+/// names the type of a serialized filter from its magic, without decoding it
+pub fn filter_kind(bytes: &[u8]) -> Result<FilterKind, DecodeError> {
+    match bytes.get(..4) {
+        Some(b"B1XF") => Ok(FilterKind::Bloom1X),
+        Some(b"B1XC") => Ok(FilterKind::Bloom1Counter),
+        Some(_) => Err(DecodeError::BadMagic),
+        None => Err(DecodeError::Truncated)
+    }
+}
+
+/// rebuilds a filter serialized by MembershipFilter::to_bytes, picking the type from the magic
+pub fn filter_from_bytes(bytes: &[u8]) -> Result<Box<dyn MembershipFilter>, DecodeError> {
+    match filter_kind(bytes)? {
+        FilterKind::Bloom1X => Ok(Box::new(Bloom1X::from_bytes(bytes)?)),
+        FilterKind::Bloom1Counter => Ok(Box::new(Bloom1Counter::from_bytes(bytes)?))
+    }
+}
@@ -0,0 +1,144 @@
+//! derivation of the row index and of the sub-hash bits from a hash digest
+//! shared by all filters of the crate
+
+/// extra hash bits consumed by the row index when `l` is not a power of two
+/// the multiply-shift reduction then favours some rows by at most l / 2^row_bits < 2^-8
+pub(crate) const ROW_GUARD_BITS: usize = 8;
+
+/// number of bits to be extracted from the hash for computing the row index
+/// a power of two `l` consumes exactly log2(l) bits, any other `l` consumes
+/// ceil(log2(l)) + ROW_GUARD_BITS bits, rounded up so that the remaining bits split evenly into k sub-hashes
+pub(crate) fn row_bits(k: usize, l: usize, h: usize) -> usize {
+    row_bits_per_row(k, l, h, 1)
+}
+
+/// number of bits to be extracted from the hash for each of g row indexes
+/// same rules as row_bits, with g * row_bits bits taken before the sub-hashes
+pub(crate) fn row_bits_per_row(k: usize, l: usize, h: usize, g: usize) -> usize {
+    if l.is_power_of_two() {
+        return l.ilog2() as usize;
+    }
+    let mut row_bits = (usize::BITS - (l - 1).leading_zeros()) as usize + ROW_GUARD_BITS;
+    while g * row_bits < h && !(h - g * row_bits).is_multiple_of(k) {
+        row_bits += 1;
+    }
+    row_bits
+}
+
+/// reads the row index from the top `row_bits` bits of digest[2]
+/// power of two row counts use the bits as they are, any other row count
+/// maps them onto 0..l with a multiply-shift range reduction
+#[inline(always)]
+pub(crate) fn row_index(digest: &[u32; 3], l: usize, row_bits: usize) -> usize {
+    let top = (digest[2] as u64) >> (32 - row_bits);
+    if l.is_power_of_two() {
+        top as usize
+    } else {
+        ((top * l as u64) >> row_bits) as usize
+    }
+}
+
+/// reads row index j of a key spreading its bits over several rows
+/// row j uses the `row_bits` bits found below the ones of row j - 1, starting from the top of digest[2],
+/// so row 0 is the row index of Bloom1X
+#[inline(always)]
+pub(crate) fn nth_row_index(digest: &[u32; 3], l: usize, row_bits: usize, j: usize) -> usize {
+    let word = digest[0] as u128 | ((digest[1] as u128) << 32) | ((digest[2] as u128) << 64);
+    let top = ((word >> (96 - (j + 1) * row_bits)) as u64) & ((1u64 << row_bits) - 1);
+    if l.is_power_of_two() {
+        top as usize
+    } else {
+        ((top * l as u64) >> row_bits) as usize
+    }
+}
+
+/// packs the digest bits left after g row indexes into a single word
+/// for g = 1 this is the same word as sub_hashes
+#[inline(always)]
+pub(crate) fn sub_hashes_after_rows(digest: &[u32; 3], row_bits: usize, g: usize) -> u128 {
+    let word = digest[0] as u128 | ((digest[1] as u128) << 32) | ((digest[2] as u128) << 64);
+    word & ((1u128 << (96 - g * row_bits)) - 1)
+}
+
+/// packs the digest bits left after the row index into a single word
+/// the sub-hash i is found at bit offset i * hash_bits
+#[inline(always)]
+pub(crate) fn sub_hashes(digest: &[u32; 3], row_bits: usize) -> u128 {
+    let low_bits = digest[2] as u64 & ((1u64 << (32 - row_bits)) - 1);
+    digest[0] as u128 | ((digest[1] as u128) << 32) | ((low_bits as u128) << 64)
+}
+
+/// selects how a sub-hash is turned into a position inside a row of `w` positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexMode {
+    /// derivation used by the hardware implementation: the sub-hash word is shifted by i * hash_bits,
+    /// truncated to 64 bits and reduced modulo w
+    /// when w is not a power of two the low 2^b mod w positions of the row are picked more often,
+    /// e.g. for w = 96 and 20 bit sub-hashes positions 0..63 are hit 10923 times out of 2^20 and positions 64..95 only 10922 times
+    #[default]
+    Modulo,
+    /// uses exactly hash_bits bits of sub-hash i and maps them onto the row as (sub_hash * w) >> hash_bits
+    /// position p receives the sub-hashes in [p * 2^b / w, (p + 1) * 2^b / w), so every position is picked
+    /// floor(2^b / w) or ceil(2^b / w) times out of 2^b, the heavier positions are spread evenly over the row
+    /// and no position deviates from 1/w by more than a relative w / 2^b
+    MultiplyShift,
+}
+
+/// position inside a row of `w` positions for sub-hash `i`
+/// a partitioned row is split into k segments of w / k positions and sub-hash i is confined to segment i
+#[inline(always)]
+pub(crate) fn row_position(sub_hashes: u128, i: usize, hash_bits: usize, w: usize, k: usize, mode: IndexMode, partitioned: bool) -> usize {
+    if partitioned {
+        let segment = w / k;
+        i * segment + sub_hash_index(sub_hashes, i, hash_bits, segment, mode)
+    } else {
+        sub_hash_index(sub_hashes, i, hash_bits, w, mode)
+    }
+}
+
+/// position inside a row of `w` positions for sub-hash `i`
+#[inline(always)]
+pub(crate) fn sub_hash_index(sub_hashes: u128, i: usize, hash_bits: usize, w: usize, mode: IndexMode) -> usize {
+    match mode {
+        IndexMode::Modulo => (sub_hashes >> (i * hash_bits)) as usize % w,
+        IndexMode::MultiplyShift => {
+            let sub_hash = (sub_hashes >> (i * hash_bits)) & ((1u128 << hash_bits) - 1);
+            ((sub_hash * w as u128) >> hash_bits) as usize
+        }
+    }
+}
+
+/// 16 bit fingerprint mixing all bits of the digest
+/// used by the strict mode of Bloom1Counter to tell keys sharing the same counters apart
+#[inline(always)]
+pub(crate) fn fingerprint(digest: &[u32; 3]) -> u16 {
+    let folded = digest[0] ^ digest[1].rotate_left(11) ^ digest[2].rotate_left(22);
+    (folded.wrapping_mul(0x9E37_79B1) >> 16) as u16
+}
+
+impl IndexMode {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            IndexMode::Modulo => 0,
+            IndexMode::MultiplyShift => 1
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(IndexMode::Modulo),
+            1 => Some(IndexMode::MultiplyShift),
+            _ => None
+        }
+    }
+}
+
+/// checks the parameters accepted by the filter constructors without panicking
+/// used when decoding filters from untrusted buffers and to validate user supplied geometries
+pub fn geometry_is_valid(k: usize, l: usize, w: usize, h: usize) -> bool {
+    if !(32..=256).contains(&w) || !w.is_multiple_of(8) || l < 1024 || l >= u32::MAX as usize || k < 2 || h <= 64 {
+        return false;
+    }
+    let row_bits = row_bits(k, l, h);
+    row_bits <= 32 && row_bits < h && (h - row_bits).is_multiple_of(k)
+}
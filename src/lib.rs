@@ -1,10 +1,41 @@
+pub mod aging;
+pub mod bank;
 pub mod bloom;
 pub mod bloom_counter;
+pub mod bloom_g;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod codec;
+pub mod digest;
+pub mod filter;
+pub mod index;
+pub mod persist;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod retouch;
+pub mod rotating;
+pub mod scalable;
+#[cfg(feature = "shared")]
+pub mod shared;
+pub mod stable;
+pub mod two_choice;
 
 #[cfg(test)]
 mod tests {
-    use crate::bloom::Bloom1X;
-    use crate::bloom_counter::Bloom1Counter;
+    use crate::aging::{Aging, AgingScheduler};
+    use crate::bank::Bloom1XBank;
+    use crate::bloom::{Bloom1X, InsertResult};
+    use crate::bloom_g::BloomG;
+    use crate::bloom_counter::{Bloom1Counter, CounterWidth, RemoveError};
+    use crate::codec::DecodeError;
+    use crate::digest::{hash_bytes, hash_u64};
+    use crate::filter::{filter_from_bytes, DeletableFilter, MembershipFilter};
+    use crate::index::IndexMode;
+    use crate::retouch::RetouchPolicy;
+    use crate::rotating::RotatingBloom1X;
+    use crate::scalable::ScalableBloom1X;
+    use crate::stable::StableBloom1;
+    use crate::two_choice::TwoChoiceBloom1X;
 
     #[test]
     fn filter_membership() {
@@ -228,52 +259,907 @@ mod tests {
         //generate first 2^16 numbers
         //and increment counters once
         for i in 0..(u16::MAX as u32 + 1) {
-            assert!(bloom_filter.query_and_inc_u32(i) == 0);
+            assert!(bloom_filter.query_and_inc_u32(i).and_result == 0);
             let current_filter = bloom_filter.get_counters_u32(i);
             //println!("Index {i} -> Counter Result: {:?}", current_filter);
-            for k in 0..4{
-                assert!(current_filter.counters[k] >= 1);
-            }
+            assert!(current_filter.iter().all(|&c| c >= 1));
         }
 
         //increment counters twice
         for i in 0..(u16::MAX as u32 + 1) {
-            assert!(bloom_filter.query_and_inc_u32(i) == 1);
+            assert!(bloom_filter.query_and_inc_u32(i).and_result == 1);
             let current_filter = bloom_filter.get_counters_u32(i);
             //println!("Index {i} -> Counter Result: {:?}", current_filter);
-            for k in 0..4{
-                assert!(current_filter.counters[k] >= 2);
-            }
+            assert!(current_filter.iter().all(|&c| c >= 2));
         }
 
         //check counters again through different function
         for i in 0..(u16::MAX as u32 + 1) {
             assert!(bloom_filter.query_u32(i) == 1);
             let current_filter = bloom_filter.get_counters_u32(i);
-            for k in 0..4{
-                assert!(current_filter.counters[k] >= 2);
-            }
+            assert!(current_filter.iter().all(|&c| c >= 2));
         }
 
          //decrement counters once
         for i in 0..(u16::MAX as u32 + 1) {
-            assert!(bloom_filter.query_and_dec_u32(i) == 1);
+            assert!(bloom_filter.query_and_dec_u32(i).and_result == 1);
             let current_filter = bloom_filter.get_counters_u32(i);
             //println!("Index {i} -> Counter Result: {:?}", current_filter);
         }
 
         //decrement counters twice
         for i in 0..(u16::MAX as u32 + 1) {
-            assert!(bloom_filter.query_and_dec_u32(i) == 1);
+            assert!(bloom_filter.query_and_dec_u32(i).and_result == 1);
             let current_filter = bloom_filter.get_counters_u32(i);
             //println!("Index {i} -> Counter Result: {:?}", current_filter);
-            for k in 0..4{
-                assert!(current_filter.counters[k] == 0);
-            }
+            assert!(current_filter.iter().all(|&c| c == 0));
         }
 
         for i in 0..(u16::MAX as u32 + 1) {
             assert!(bloom_filter.query_u32(i) == 0);
         }
     }
+
+    #[test]
+    fn non_power_of_two_rows() {
+        //3 BRAM blocks worth of rows
+        let mut bloom_filter = Bloom1X::new(4, 3 * 1024, 96, 96);
+
+        let mut hits = vec![0usize; 3 * 1024];
+        for i in 0..30000u64 {
+            let qr = bloom_filter.search_u64(i + 0xDEADBEEF);
+            hits[qr.row_index] += 1;
+            bloom_filter.query_and_set_u64(i + 0xDEADBEEF);
+        }
+
+        for i in 0..30000u64 {
+            assert!(bloom_filter.query_u64(i + 0xDEADBEEF) == 1);
+        }
+
+        //every third of the filter should receive about a third of the keys
+        for third in hits.chunks(1024) {
+            let count: usize = third.iter().sum();
+            assert!(count > 9000 && count < 11000);
+        }
+    }
+
+    #[test]
+    fn multiply_shift_index_mode() {
+        let mut bloom_filter = Bloom1X::new(4, u16::MAX as usize + 1, 96, 96).with_index_mode(IndexMode::MultiplyShift);
+
+        //low and high halves of the row should be hit equally often
+        let mut low = 0usize;
+        for i in 0..100_000u64 {
+            let qr = bloom_filter.search_u64(i);
+            low += qr.bit_indexes.iter().filter(|&&b| b < 48).count();
+            bloom_filter.query_and_set_u64(i);
+        }
+        assert!(low > 195_000 && low < 205_000);
+
+        for i in 0..100_000u64 {
+            assert!(bloom_filter.query_u64(i) == 1);
+        }
+    }
+
+    #[test]
+    fn saturating_counters() {
+        let mut bloom_filter = Bloom1Counter::new(4, 4096, 32, 96);
+
+        //pick a key whose sub-hashes land on 4 distinct counters
+        let key = (0xDEADBEEF..).find(|&key| {
+            let mut indexes = bloom_filter.search_u64(key).counter_indexes;
+            indexes.sort();
+            indexes.dedup();
+            indexes.len() == 4
+        }).unwrap();
+
+        //counters stick at the maximum value instead of wrapping
+        for _ in 0..300 {
+            bloom_filter.query_and_inc_u64(key);
+        }
+        let update = bloom_filter.query_and_inc_u64(key);
+        assert!(update.and_result == 1 && update.saturated == 4);
+        for _ in 0..300 {
+            let update = bloom_filter.query_and_dec_u64(key);
+            assert!(update.saturated == 4 && update.underflowed == 0);
+        }
+        assert!(bloom_filter.query_u64(key) == 1);
+
+        //removing an absent key leaves its counters at 0
+        let update = bloom_filter.query_and_dec_u64(0xC0FFEE);
+        assert!(update.and_result == 0 && update.underflowed == 4);
+        assert!(bloom_filter.get_counters_u64(0xC0FFEE).iter().all(|&c| c == 0));
+
+        //the overflow stash keeps exact counts beyond the maximum
+        let mut bloom_filter = Bloom1Counter::new(4, 4096, 32, 96).with_overflow_stash();
+        for _ in 0..300 {
+            bloom_filter.query_and_inc_u64(key);
+        }
+        for i in 0..300 {
+            let update = bloom_filter.query_and_dec_u64(key);
+            assert!(update.and_result == 1);
+            assert!((update.saturated == 4) == (i < 45));
+        }
+        assert!(bloom_filter.query_u64(key) == 0);
+    }
+
+    #[test]
+    fn packed_counter_widths() {
+        for width in [CounterWidth::Bits2, CounterWidth::Bits4, CounterWidth::Bits8, CounterWidth::Bits16] {
+            let mut bloom_filter = Bloom1Counter::new(4, 4096, 96, 96).with_counter_width(width);
+            assert!(bloom_filter.memory_size() == 4096 * 96 * width.bits() / 8);
+
+            for i in 0..20000u64 {
+                bloom_filter.query_and_inc_u64(i);
+                assert!(bloom_filter.query_u64(i) == 1);
+            }
+
+            //counters saturate at the maximum of their width without disturbing their neighbours
+            let key = 0xDEADBEEF;
+            for _ in 0..70000 {
+                bloom_filter.query_and_inc_u64(key);
+            }
+            let counters = bloom_filter.get_counters_u64(key);
+            assert!(counters.iter().all(|&c| c == width.max()));
+            for i in 0..20000u64 {
+                assert!(bloom_filter.query_u64(i) == 1);
+            }
+
+            bloom_filter.clear();
+            for i in 0..20000u64 {
+                bloom_filter.query_and_inc_u64(i);
+                bloom_filter.query_and_dec_u64(i);
+            }
+            assert!((0..20000u64).all(|i| bloom_filter.query_u64(i) == 0));
+        }
+    }
+
+    #[test]
+    fn safe_remove() {
+        let mut bloom_filter = Bloom1Counter::new(4, 4096, 96, 96);
+
+        for i in 0..10000u64 {
+            bloom_filter.query_and_inc_u64(i);
+        }
+
+        //absent keys are refused and leave the counters untouched
+        let absent = (10000..).find(|&i| bloom_filter.query_u64(i) == 0).unwrap();
+        assert!(bloom_filter.try_remove_u64(absent) == Err(RemoveError::NotPresent));
+        for i in 0..10000u64 {
+            assert!(bloom_filter.remove_u64(i));
+        }
+        assert!((0..10000u64).all(|i| bloom_filter.query_u64(i) == 0));
+
+        //in strict mode a key colliding on all counters but never inserted is refused
+        let mut bloom_filter = Bloom1Counter::new(4, 4096, 96, 96).with_strict_mode();
+        bloom_filter.query_and_inc_u64(0xDEADBEEF);
+        let mut impostor = bloom_filter.query_u64_with_result(0xDEADBEEF);
+        impostor.fingerprint = impostor.fingerprint.wrapping_add(1);
+        assert!(bloom_filter.remove_by_result(&impostor) == Err(RemoveError::NotInserted));
+        assert!(bloom_filter.query_u64(0xDEADBEEF) == 1);
+        assert!(bloom_filter.try_remove_u64(0xDEADBEEF).is_ok());
+        assert!(bloom_filter.query_u64(0xDEADBEEF) == 0);
+
+        //a refused removal leaves counters, stash and checksums exactly as they were,
+        //here a key whose sub-hashes share one counter drains the row that still counts other keys
+        for inserts in [2, 4] {
+            let mut bloom_filter = Bloom1Counter::new(4, 4096, 32, 96).with_counter_width(CounterWidth::Bits2)
+                .with_overflow_stash().with_conservative_update().with_strict_mode();
+            let mut shared = bloom_filter.query_u64_with_result(0xDEADBEEF);
+            shared.counter_indexes = vec![shared.counter_indexes[0]; 4];
+            for _ in 0..inserts {
+                bloom_filter.inc_counters(&shared);
+            }
+            let before = bloom_filter.to_bytes();
+            assert!(bloom_filter.remove_by_result(&shared) == Err(RemoveError::NotInserted));
+            assert!(bloom_filter.to_bytes() == before);
+        }
+    }
+
+    #[test]
+    fn project_counters_to_bits() {
+        let mut counting_filter = Bloom1Counter::new(4, 4096, 96, 96).with_counter_width(CounterWidth::Bits4);
+        let mut bloom_filter = Bloom1X::new(4, 4096, 96, 96);
+
+        for i in 0..20000u64 {
+            counting_filter.query_and_inc_u64(i);
+        }
+        bloom_filter.apply_row_updates(&counting_filter.project_changes());
+        let projection = counting_filter.to_bloom1x();
+        assert!(projection.filter == bloom_filter.filter);
+        for i in 0..40000u64 {
+            assert!(projection.query_u64(i) == counting_filter.query_u64(i));
+        }
+
+        //only rows touched by the removals are shipped again
+        for i in 0..100u64 {
+            assert!(counting_filter.remove_u64(i));
+        }
+        let updates = counting_filter.project_changes();
+        assert!(!updates.is_empty() && updates.len() <= 100);
+        bloom_filter.apply_row_updates(&updates);
+        assert!(counting_filter.to_bloom1x().filter == bloom_filter.filter);
+        assert!(counting_filter.project_changes().is_empty());
+    }
+
+    #[test]
+    fn frequency_estimates() {
+        let mut standard = Bloom1Counter::new(4, 1 << 12, 96, 96);
+        let mut conservative = Bloom1Counter::new(4, 1 << 12, 96, 96).with_conservative_update();
+
+        //key i is inserted (i % 10) + 1 times
+        for i in 0..20000u64 {
+            for _ in 0..(i % 10) + 1 {
+                standard.query_and_inc_u64(i);
+                conservative.query_and_inc_u64(i);
+            }
+        }
+
+        let mut standard_error = 0;
+        let mut conservative_error = 0;
+        for i in 0..20000u64 {
+            let count = (i % 10) + 1;
+            assert!(standard.estimate_count_u64(i) >= count);
+            assert!(conservative.estimate_count_u64(i) >= count);
+            assert!(standard.query_at_least_u64(i, count) == 1);
+            standard_error += standard.estimate_count_u64(i) - count;
+            conservative_error += conservative.estimate_count_u64(i) - count;
+        }
+        assert!(conservative_error <= standard_error);
+        println!("Overestimation: standard = {}, conservative = {}", standard_error, conservative_error);
+
+        let absent = (20000..).find(|&i| standard.query_u64(i) == 0).unwrap();
+        assert!(standard.estimate_count_u64(absent) == 0);
+        assert!(standard.query_at_least_u64(absent, 1) == 0);
+    }
+
+    #[test]
+    fn counter_aging() {
+        let mut bloom_filter = Bloom1Counter::new(4, 4096, 96, 96).with_overflow_stash();
+        for i in 0..1000u64 {
+            for _ in 0..300 {
+                bloom_filter.query_and_inc_u64(i);
+            }
+        }
+
+        bloom_filter.age(Aging::Halve);
+        assert!((0..1000u64).all(|i| bloom_filter.estimate_count_u64(i) >= 150));
+        bloom_filter.age(Aging::Subtract(100));
+        assert!((0..1000u64).all(|i| bloom_filter.estimate_count_u64(i) >= 50));
+
+        //spreading the aging over four calls ages every row exactly once
+        for _ in 0..4 {
+            bloom_filter.age_fraction(Aging::Subtract(1000), 0.25);
+        }
+        assert!((0..1000u64).all(|i| bloom_filter.query_u64(i) == 0));
+
+        //the scheduler halves the filter once per 1000 insertions, a quarter at a time
+        let mut scheduler = AgingScheduler::new(1000, Aging::Halve).with_fraction(0.25);
+        let mut steps = 0;
+        for i in 0..4000u64 {
+            bloom_filter.query_and_inc_u64(i % 10);
+            if scheduler.record_insertion(&mut bloom_filter) {
+                steps += 1;
+            }
+        }
+        assert!(steps == 16);
+        assert!((0..10u64).all(|i| bloom_filter.estimate_count_u64(i) < 400));
+    }
+
+    #[test]
+    fn stable_filter_on_unbounded_stream() {
+        let mut bloom_filter = StableBloom1::new(4, 4096, 96, 96, 3, 10);
+        assert!(bloom_filter.memory_size() == 4096 * 96 * 2 / 8);
+
+        //stream far more distinct keys than the filter could ever hold
+        for i in 0..2_000_000u64 {
+            bloom_filter.query_and_set_u64(i);
+            //the latest key is always reported
+            assert!(bloom_filter.query_u64(i) == 1);
+        }
+
+        let mut count = 0;
+        for i in 2_000_000..2_100_000u64 {
+            count += bloom_filter.query_u64(i) as usize;
+        }
+        let fpr = count as f64 / 100_000.0;
+        let predicted = bloom_filter.predicted_false_positive_rate();
+        println!("Stable false positive rate = {}, predicted = {}", fpr, predicted);
+        assert!(fpr < 0.5);
+        assert!((fpr - predicted).abs() < 0.05);
+    }
+
+    #[test]
+    fn rotating_generations() {
+        let mut bloom_filter = RotatingBloom1X::new(4, 4096, 96, 96, 3);
+
+        //one batch of keys per generation
+        for batch in 0..3u64 {
+            for i in batch * 1000..(batch + 1) * 1000 {
+                assert!(bloom_filter.query_and_set_u64(i) == 0);
+            }
+            bloom_filter.rotate();
+        }
+        //the first batch was dropped by the last rotation
+        assert!((1000..3000u64).all(|i| bloom_filter.query_u64(i) == 1));
+        assert!((0..1000u64).filter(|&i| bloom_filter.query_u64(i) == 1).count() < 50);
+
+        bloom_filter.rotate();
+        bloom_filter.rotate();
+        assert!((0..3000u64).all(|i| bloom_filter.query_u64(i) == 0));
+    }
+
+    #[test]
+    fn scalable_growth() {
+        let mut bloom_filter = ScalableBloom1X::new(4, 1024, 96, 96);
+
+        //far more keys than the first stage can hold
+        for i in 0..200_000u64 {
+            bloom_filter.query_and_set_u64(i);
+        }
+        assert!(bloom_filter.stages().len() > 3);
+        assert!((0..200_000u64).all(|i| bloom_filter.query_u64(i) == 1));
+
+        let mut count = 0;
+        for i in 200_000..300_000u64 {
+            count += bloom_filter.query_u64(i) as usize;
+        }
+        let fpr = count as f64 / 100_000.0;
+        println!("Scalable false positive rate = {}, estimated = {}, target = {}", fpr, bloom_filter.estimated_false_positive_rate(), bloom_filter.target_false_positive_rate());
+        let target = bloom_filter.target_false_positive_rate();
+        assert!(bloom_filter.estimated_false_positive_rate() <= target);
+        assert!(fpr < target + 3.0 * (target / 100_000.0).sqrt());
+
+        //wide rows run out of sub-hash bits after a few stages, the bound holds past that point
+        let mut wide = ScalableBloom1X::new(8, 1024, 256, 96);
+        for i in 0..2_000_000u64 {
+            wide.query_and_set_u64(i);
+        }
+        let ks: Vec<usize> = wide.stages().iter().map(|stage| stage.k()).collect();
+        assert!(ks.len() > 5 && ks[ks.len() - 3..].iter().all(|&k| k == ks[ks.len() - 1]));
+        let mut count = 0;
+        for i in 10_000_000..10_200_000u64 {
+            count += wide.query_u64(i) as usize;
+        }
+        let fpr = count as f64 / 200_000.0;
+        let target = wide.target_false_positive_rate();
+        println!("Scalable false positive rate with stages k = {:?}: {}, target = {}", ks, fpr, target);
+        assert!(wide.estimated_false_positive_rate() <= target);
+        assert!(fpr < target + 3.0 * (target / 200_000.0).sqrt());
+
+        //the stage list survives a serialization round trip
+        let bytes = bloom_filter.to_bytes();
+        let restored = ScalableBloom1X::from_bytes(&bytes).unwrap();
+        assert!(restored.stages().len() == bloom_filter.stages().len());
+        assert!(restored.to_bytes() == bytes);
+        assert!((0..300_000u64).all(|i| restored.query_u64(i) == bloom_filter.query_u64(i)));
+        assert!(ScalableBloom1X::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        //stages that the filter would not have grown are refused rather than failing on a later insert
+        let stage = |k: usize, l: usize, h: usize| {
+            let mut buffer = vec![];
+            Bloom1X::new(k, l, 96, h).encode(&mut buffer);
+            buffer
+        };
+        let crafted = |stages: &[Vec<u8>]| {
+            let mut buffer = bytes[..25].to_vec();
+            buffer[21..25].copy_from_slice(&(stages.len() as u32).to_le_bytes());
+            stages.iter().for_each(|stage| buffer.extend_from_slice(stage));
+            ScalableBloom1X::from_bytes(&buffer)
+        };
+        assert!(crafted(&[stage(4, 1024, 94), stage(5, 2048, 96)]).is_ok());
+        assert!(matches!(crafted(&[]), Err(DecodeError::InvalidParameters)));
+        assert!(matches!(crafted(&[stage(4, 1536, 94)]), Err(DecodeError::InvalidParameters)));
+        assert!(matches!(crafted(&[stage(4, 1024, 90)]), Err(DecodeError::InvalidParameters)));
+        assert!(matches!(crafted(&[stage(4, 1024, 94), stage(4, 2048, 95)]), Err(DecodeError::InvalidParameters)));
+        assert!(matches!(crafted(&[stage(4, 1024, 94), stage(5, 4096, 97)]), Err(DecodeError::InvalidParameters)));
+        assert!(matches!(crafted(&[stage(4, 1024, 94), stage(4, 1024, 94)]), Err(DecodeError::InvalidParameters)));
+    }
+
+    #[test]
+    fn bloom_g_rows() {
+        //a single row per key reproduces Bloom1X bit for bit
+        let mut bloom_1 = Bloom1X::new(4, 3 * 1024, 96, 96);
+        let mut bloom_g = BloomG::new(4, 3 * 1024, 96, 96, 1);
+        for i in 0..20000u64 {
+            assert!(bloom_1.query_and_set_u64(i) == bloom_g.query_and_set_u64(i));
+        }
+        assert!(bloom_1.filter == bloom_g.filter);
+
+        //two rows per key lower the false positive rate for the same memory
+        //with k = 6 and 20000 keys in 4096 rows of 96 bits the expected rates are 1.27e-3 for g = 1
+        //and 4.7e-4 for g = 2, a gap that a million probes measures far from the sampling noise
+        let mut bloom_1 = Bloom1X::new(6, 4096, 96, 96);
+        let mut bloom_2 = BloomG::new(6, 4096, 96, 96, 2);
+        for i in 0..20000u64 {
+            bloom_1.query_and_set_u64(i);
+            bloom_2.query_and_set_u64(i);
+            assert!(bloom_2.search_u64(i).row_indexes.len() == 2);
+        }
+        let mut count_1 = 0;
+        let mut count_2 = 0;
+        for i in 1_000_000..2_000_000u64 {
+            count_1 += bloom_1.query_u64(i) as usize;
+            count_2 += bloom_2.query_u64(i) as usize;
+        }
+        println!("False positives: g = 1 -> {}, g = 2 -> {}", count_1, count_2);
+        assert!(count_2 * 3 < count_1 * 2);
+        assert!((0..20000u64).all(|i| bloom_2.query_u64(i) == 1));
+    }
+
+    #[test]
+    fn partitioned_rows() {
+        let mut bloom_filter = Bloom1X::new(4, 4096, 96, 96).with_partitioned_rows();
+
+        for i in 0..20000u64 {
+            //sub-hash i always lands in segment i of the row
+            let qr = bloom_filter.search_u64(i);
+            for (segment, &bit_index) in qr.bit_indexes.iter().enumerate() {
+                assert!(bit_index / 24 == segment);
+            }
+            let result = bloom_filter.query_u64_with_result(i);
+            assert!(result.bit_indexes == qr.bit_indexes && result.row_index == qr.row_index);
+            bloom_filter.update_filter(&result);
+        }
+        assert!((0..20000u64).all(|i| bloom_filter.query_u64(i) == 1));
+
+        let restored = Bloom1X::from_bytes(&bloom_filter.to_bytes()).unwrap();
+        assert!((0..40000u64).all(|i| restored.query_u64(i) == bloom_filter.query_u64(i)));
+
+        let mut counting_filter = Bloom1Counter::new(4, 4096, 96, 96).with_partitioned_rows();
+        for i in 0..20000u64 {
+            counting_filter.query_and_inc_u64(i);
+        }
+        assert!(counting_filter.to_bloom1x().filter == bloom_filter.filter);
+    }
+
+    #[test]
+    fn two_choice_rows() {
+        for (k, n) in [(4, 10000u64), (4, 40000), (6, 10000), (6, 40000)] {
+            let mut standard = Bloom1X::new(k, 4096, 96, 96);
+            let mut two_choice = TwoChoiceBloom1X::new(k, 4096, 96, 96);
+            assert!(standard.memory_size() == two_choice.memory_size());
+
+            for i in 0..n {
+                standard.query_and_set_u64(i);
+                two_choice.query_and_set_u64(i);
+            }
+            assert!((0..n).all(|i| two_choice.query_u64(i) == 1));
+
+            let mut count_standard = 0;
+            let mut count_two_choice = 0;
+            for i in 1u64 << 40..(1u64 << 40) + 2_000_000 {
+                count_standard += standard.query_u64(i) as usize;
+                count_two_choice += two_choice.query_u64(i) as usize;
+            }
+            println!(
+                "k = {}, n = {}: standard FPR = {}, two choices FPR = {}",
+                k,
+                n,
+                count_standard as f64 / 2_000_000.0,
+                count_two_choice as f64 / 2_000_000.0
+            );
+            //lightly loaded rows with many hashes is where the flatter load wins,
+            //heavily loaded rows is where checking two rows loses
+            if k == 6 && n == 10000 {
+                assert!(count_two_choice * 3 < count_standard * 2);
+            }
+            if n == 40000 {
+                assert!(count_two_choice * 5 > count_standard * 6);
+            }
+        }
+    }
+
+    #[test]
+    fn retouch_false_positives() {
+        let mut bloom_filter = Bloom1X::new(2, 1024, 32, 96).with_recency_hints();
+        for i in 0..4000u64 {
+            bloom_filter.query_and_set_u64(i);
+        }
+        let protected: Vec<_> = (0..4000u64).map(|i| bloom_filter.search_u64(i)).collect();
+        let false_positives: Vec<u64> = (4000..100_000u64).filter(|&i| bloom_filter.query_u64(i) == 1).take(30).collect();
+        assert!(false_positives.len() == 30);
+
+        let policies = [RetouchPolicy::Random(7), RetouchPolicy::LeastShared, RetouchPolicy::LeastRecentlySet];
+        let mut reported = 0;
+        for (i, &key) in false_positives.iter().enumerate() {
+            let report = bloom_filter.retouch_u64(key, policies[i % 3], Some(&protected)).unwrap();
+            assert!(bloom_filter.query_u64(key) == 0);
+            reported += report.false_negatives.unwrap();
+        }
+
+        //the reports account for every inserted key that was lost
+        let lost = (0..4000u64).filter(|&i| bloom_filter.query_u64(i) == 0).count();
+        assert!(lost == reported);
+        assert!(bloom_filter.retouch_u64(false_positives[0], RetouchPolicy::LeastShared, None).is_none());
+
+        //without recency hints the least recently set bit is unknown and nothing is cleared
+        let mut bloom_filter = Bloom1X::new(2, 1024, 32, 96);
+        bloom_filter.query_and_set_u64(1);
+        assert!(bloom_filter.retouch_u64(1, RetouchPolicy::LeastRecentlySet, None).is_none());
+        assert!(bloom_filter.query_u64(1) == 1);
+    }
+
+    #[test]
+    fn membership_filter_trait() {
+        let mut filters: Vec<Box<dyn MembershipFilter>> = vec![
+            Box::new(Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows()),
+            Box::new(Bloom1Counter::new(4, 4096, 64, 96).with_counter_width(CounterWidth::Bits4).with_strict_mode()),
+        ];
+        for filter in filters.iter_mut() {
+            for i in 0..2000u64 {
+                filter.insert_u64(i);
+            }
+            assert!(filter.insert_bytes(b"trait key") == 0);
+            assert!(filter.contains_bytes(b"trait key") == 1);
+            assert!((0..2000u64).all(|i| filter.contains_u64(i) == 1));
+
+            //the decoded filter answers like the original one
+            let decoded = filter_from_bytes(&filter.to_bytes()).unwrap();
+            assert!(decoded.parameters() == filter.parameters());
+            assert!(decoded.memory_size() == filter.memory_size());
+            assert!((0..20_000u64).all(|i| decoded.contains_u64(i) == filter.contains_u64(i)));
+
+            filter.clear();
+            assert!(filter.contains_bytes(b"trait key") == 0);
+        }
+        assert!(filter_from_bytes(b"XXXX").is_err());
+
+        let mut counter = Bloom1Counter::new(4, 4096, 64, 96).with_overflow_stash();
+        for i in 0..300u64 {
+            counter.insert_u64(i);
+            counter.insert_u64(i % 3);
+        }
+        let mut decoded = Bloom1Counter::from_bytes(&counter.to_bytes()).unwrap();
+        assert!(decoded.to_bytes() == counter.to_bytes());
+        let deletable: &mut dyn DeletableFilter = &mut decoded;
+        for i in 0..300u64 {
+            assert!(deletable.remove_u64(i).is_ok());
+        }
+        assert!(deletable.contains_u64(1) == 1);
+        let absent = (1000..2000u64).find(|&i| deletable.contains_u64(i) == 0).unwrap();
+        assert!(deletable.remove_u64(absent) == Err(RemoveError::NotPresent));
+    }
+
+    #[test]
+    fn insert_detailed() {
+        let mut bloom_filter = Bloom1X::new(4, 4096, 64, 96);
+        let first = bloom_filter.insert_detailed_u64(7);
+        assert!(first.and_result == 0 && first.row_was_empty);
+        assert!(first.bits_set >= 1 && first.bits_set <= 4);
+        assert!(bloom_filter.insert_detailed_u64(7) == InsertResult { and_result: 1, bits_set: 0, row_was_empty: false });
+
+        let mut new_bits = first.bits_set;
+        for i in 0..20_000u64 {
+            let result = bloom_filter.insert_detailed_u64(i);
+            assert!((result.bits_set == 0) == (result.and_result == 1));
+            assert!(!result.row_was_empty || result.bits_set > 0);
+            new_bits += result.bits_set;
+        }
+        //every bit set in the filter was reported exactly once
+        assert!(new_bits == bloom_filter.count_ones());
+        assert!(bloom_filter.insert_detailed_bytes(b"duplicate").bits_set > 0);
+        assert!(bloom_filter.insert_detailed_bytes(b"duplicate").bits_set == 0);
+    }
+
+    #[test]
+    fn precomputed_digests() {
+        let mut small = Bloom1X::new(4, 4096, 64, 96);
+        let mut large = Bloom1X::new(4, 3 * 4096, 128, 96).with_index_mode(IndexMode::MultiplyShift);
+        let mut counter = Bloom1Counter::new(4, 4096, 64, 96);
+        for i in 0..5000u64 {
+            let digest = hash_u64(i);
+            let before = small.query_u64(i);
+            assert!(small.insert_digest(&digest) == before);
+            large.insert_digest(&digest);
+            counter.insert_digest(&digest);
+        }
+
+        //a digest answers like the key it was computed from, whatever the geometry
+        for i in 0..20_000u64 {
+            let digest = hash_u64(i);
+            assert!(small.contains_digest(&digest) == small.query_u64(i));
+            assert!(large.contains_digest(&digest) == large.query_u64(i));
+            assert!(counter.contains_digest(&digest) == counter.query_u64(i));
+        }
+        let digest = hash_bytes(b"shared key");
+        let mut filters: Vec<Box<dyn MembershipFilter>> = vec![Box::new(small), Box::new(counter)];
+        for filter in filters.iter_mut() {
+            filter.insert_digest(&digest);
+            assert!(filter.contains_bytes(b"shared key") == 1);
+        }
+    }
+
+    #[test]
+    fn filter_bank() {
+        let mut bank = Bloom1XBank::new(4, 4096, 64, 96).with_partitioned_rows();
+        let mut filters = vec![];
+        for f in 0..100u64 {
+            let mut bloom_filter = Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows();
+            for i in 0..500u64 {
+                bloom_filter.query_and_set_u64(f * 300 + i);
+            }
+            assert!(bank.add(&bloom_filter) == f as usize);
+            filters.push(bloom_filter);
+        }
+        assert!(bank.len() == 100 && bank.capacity() == 128);
+
+        //one bank query answers for every member
+        for i in 0..40_000u64 {
+            let matches = bank.query_u64(i);
+            for (f, bloom_filter) in filters.iter().enumerate() {
+                assert!(((matches[f / 64] >> (f % 64)) & 1) as u8 == bloom_filter.query_u64(i));
+            }
+        }
+
+        //freed slots are reused and replaced members answer with their new bits
+        assert!(bank.remove(3) && !bank.remove(3));
+        assert!(bank.query_u64(900)[0] & (1 << 3) == 0);
+        assert!(bank.add(&Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows()) == 3);
+        bank.insert_bytes(3, b"bank key");
+        assert!(bank.query_bytes(b"bank key")[0] & (1 << 3) != 0);
+        bank.replace(70, &filters[5]);
+        assert!(bank.member(70).unwrap().to_bytes() == filters[5].to_bytes());
+        assert!(bank.member(42).unwrap().to_bytes() == filters[42].to_bytes());
+        assert!(!bank.accepts(&Bloom1X::new(4, 4096, 64, 96)));
+    }
+
+    #[cfg(feature = "capi")]
+    #[test]
+    fn c_api_header_is_current() {
+        //regenerate with BLOOM1X_UPDATE_HEADER=1 cargo build --features capi
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/bloom1x.h"));
+        assert!(generated == include_str!("../include/bloom1x.h"), "include/bloom1x.h is stale");
+    }
+
+    #[cfg(feature = "capi")]
+    #[test]
+    fn c_api_status_codes() {
+        use crate::capi::*;
+        unsafe {
+            let mut filter = std::ptr::null_mut();
+            let mut found = 0u8;
+            assert!(b1x_filter_new(4, 1000, 64, 96, 0, false, &mut filter) == B1xStatus::InvalidArgument);
+            assert!(b1x_filter_new(4, 4096, 64, 96, 1, false, &mut filter) == B1xStatus::Ok);
+            assert!(b1x_filter_insert_u64(filter, 5, &mut found) == B1xStatus::Ok && found == 0);
+            assert!(b1x_filter_query_u64(filter, 5, &mut found) == B1xStatus::Ok && found == 1);
+            assert!(b1x_filter_query_bytes(filter, [0u8; 49].as_ptr(), 49, &mut found) == B1xStatus::InvalidArgument);
+            assert!(b1x_filter_query_u64(std::ptr::null(), 5, &mut found) == B1xStatus::NullPointer);
+
+            let (mut row_index, mut bit_indexes) = (0u32, [0u32; 4]);
+            assert!(b1x_filter_search_u64(filter, 5, &mut row_index, bit_indexes.as_mut_ptr(), 3) == B1xStatus::BufferTooSmall);
+            assert!(b1x_filter_search_u64(filter, 5, &mut row_index, bit_indexes.as_mut_ptr(), 4) == B1xStatus::Ok);
+            let qr = (*filter).search_u64(5);
+            assert!(row_index as usize == qr.row_index && bit_indexes.iter().zip(&qr.bit_indexes).all(|(&a, &b)| a as usize == b));
+
+            let mut size = 0;
+            assert!(b1x_filter_serialize(filter, std::ptr::null_mut(), 0, &mut size) == B1xStatus::BufferTooSmall);
+            let mut buffer = vec![0u8; size];
+            assert!(b1x_filter_serialize(filter, buffer.as_mut_ptr(), size, &mut size) == B1xStatus::Ok);
+            let mut copy = std::ptr::null_mut();
+            assert!(b1x_filter_deserialize(buffer.as_ptr(), size - 1, &mut copy) == B1xStatus::DecodeError);
+            assert!(b1x_filter_deserialize(buffer.as_ptr(), size, &mut copy) == B1xStatus::Ok);
+            assert!((*copy).to_bytes() == buffer);
+            b1x_filter_free(filter);
+            b1x_filter_free(copy);
+
+            let mut counter = std::ptr::null_mut();
+            assert!(b1x_counter_new(4, 4096, 64, 96, 3, 0, false, &mut counter) == B1xStatus::InvalidArgument);
+            assert!(b1x_counter_new(4, 4096, 64, 96, 4, 0, false, &mut counter) == B1xStatus::Ok);
+            assert!(b1x_counter_insert_bytes(counter, b"key".as_ptr(), 3, &mut found) == B1xStatus::Ok);
+            assert!(b1x_counter_remove_bytes(counter, b"key".as_ptr(), 3) == B1xStatus::Ok);
+            assert!(b1x_counter_remove_bytes(counter, b"key".as_ptr(), 3) == B1xStatus::NotPresent);
+            b1x_counter_free(counter);
+        }
+    }
+
+    #[test]
+    fn union_and_estimates() {
+        let mut first = Bloom1X::new(4, 4096, 64, 96);
+        let mut second = Bloom1X::new(4, 4096, 64, 96);
+        for i in 0..8000u64 {
+            if i < 5000 {
+                first.query_and_set_u64(i);
+            }
+            if i >= 3000 {
+                second.query_and_set_u64(i);
+            }
+        }
+        let estimated = first.estimated_count();
+        assert!(estimated > 4500.0 && estimated < 5500.0);
+        let false_positives = (100_000..300_000u64).filter(|&i| first.query_u64(i) == 1).count() as f64 / 200_000.0;
+        println!("estimated fpr = {}, measured fpr = {}", first.estimated_false_positive_rate(), false_positives);
+
+        assert!(first.is_compatible(&second));
+        assert!(!first.is_compatible(&Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows()));
+        first.union(&second);
+        assert!((0..8000u64).all(|i| first.query_u64(i) == 1));
+        assert!((0..4096).all(|i| first.row(i).iter().zip(second.row(i)).all(|(a, b)| a & b == *b)));
+    }
+
+    #[cfg(feature = "shared")]
+    #[test]
+    fn shared_memory_filter() {
+        use crate::filter::FilterParameters;
+        use crate::shared::{self, AttachError, SharedBloom1X};
+        let path = std::env::temp_dir().join(format!("bloom1x-shared-{}", std::process::id()));
+        let _ = shared::remove(&path);
+
+        let mut bloom_filter = Bloom1X::new(4, 4096, 64, 96).with_partitioned_rows();
+        bloom_filter.query_and_set_u64(1);
+        let creator = SharedBloom1X::create(&path, &bloom_filter).unwrap();
+        assert!(SharedBloom1X::create(&path, &bloom_filter).is_err());
+
+        //two attachments of the same file see each other's inserts at once
+        let attached = SharedBloom1X::open_with(&path, FilterParameters { k: 4, l: 4096, w: 64, h: 96 }, IndexMode::Modulo, true).unwrap();
+        assert!(attached.query_u64(1) == 1);
+        let writers: Vec<_> = (0..4u64).map(|t| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let filter = SharedBloom1X::open(&path).unwrap();
+                for i in 0..2000u64 {
+                    filter.query_and_set_u64(t * 2000 + i);
+                }
+            })
+        }).collect();
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+        assert!((0..8000u64).all(|i| creator.query_u64(i) == 1 && attached.query_u64(i) == 1));
+
+        //the shared bits are those a private filter would hold
+        for i in 0..8000u64 {
+            bloom_filter.query_and_set_u64(i);
+        }
+        assert!(attached.to_bloom1x().to_bytes() == bloom_filter.to_bytes());
+
+        let geometry = FilterParameters { k: 4, l: 4096, w: 64, h: 96 };
+        assert!(matches!(SharedBloom1X::open_with(&path, FilterParameters { l: 8192, ..geometry }, IndexMode::Modulo, true), Err(AttachError::GeometryMismatch(..))));
+        //a filter hashing differently is refused too, and the error tells what the file holds
+        let mismatch = SharedBloom1X::open_with(&path, geometry, IndexMode::MultiplyShift, true);
+        assert!(matches!(mismatch, Err(AttachError::GeometryMismatch(found, IndexMode::Modulo, true)) if found == geometry));
+        assert!(matches!(SharedBloom1X::open_with(&path, geometry, IndexMode::Modulo, false), Err(AttachError::GeometryMismatch(..))));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4] = 9;
+        let corrupted = path.with_extension("corrupted");
+        std::fs::write(&corrupted, &bytes).unwrap();
+        assert!(matches!(SharedBloom1X::open(&corrupted), Err(AttachError::Invalid(_))));
+        shared::remove(&corrupted).unwrap();
+
+        shared::remove(&path).unwrap();
+        assert!(attached.query_u64(7) == 1);
+        assert!(matches!(SharedBloom1X::open(&path), Err(AttachError::Io(_))));
+    }
+
+    #[test]
+    fn write_ahead_log_recovery() {
+        use crate::persist::{PersistError, PersistentFilter};
+        let dir = std::env::temp_dir().join(format!("bloom1x-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let new_counter = || Bloom1Counter::new(4, 4096, 64, 96).with_counter_width(CounterWidth::Bits4);
+
+        let mut reference = new_counter();
+        let mut persistent = PersistentFilter::open(&dir, new_counter).unwrap();
+        for i in 0..300u64 {
+            reference.query_and_inc_u64(i);
+            persistent.insert_u64(i).unwrap();
+        }
+        for i in 0..100u64 {
+            reference.try_remove_u64(i).unwrap();
+            assert!(persistent.remove_u64(i).unwrap().is_ok());
+        }
+        //failed removes are logged and fail again on replay
+        assert!(persistent.remove_u64(5000).unwrap().is_err());
+        assert!(persistent.log_len() == 401);
+        drop(persistent);
+
+        //a torn last record is cut off, the decrements before it are kept
+        let wal = dir.join("wal");
+        let mut log = std::fs::OpenOptions::new().append(true).open(&wal).unwrap();
+        std::io::Write::write_all(&mut log, &[1, 2, 3, 4, 5]).unwrap();
+        drop(log);
+        let mut persistent = PersistentFilter::open(&dir, new_counter).unwrap();
+        assert!(persistent.filter().to_bytes() == reference.to_bytes());
+        assert!(persistent.log_len() == 401);
+
+        persistent.checkpoint().unwrap();
+        assert!(persistent.log_len() == 0);
+        persistent.insert_u64(7000).unwrap();
+        reference.query_and_inc_u64(7000);
+        drop(persistent);
+        let persistent = PersistentFilter::open(&dir, new_counter).unwrap();
+        assert!(persistent.filter().to_bytes() == reference.to_bytes());
+        assert!(persistent.log_len() == 1);
+        drop(persistent);
+
+        //a damaged record followed by complete ones is not a torn write
+        let mut bytes = std::fs::read(&wal).unwrap();
+        let record = bytes[13..30].to_vec();
+        bytes.extend_from_slice(&record);
+        bytes[14] ^= 0xff;
+        std::fs::write(&wal, &bytes).unwrap();
+        assert!(matches!(PersistentFilter::open(&dir, new_counter), Err(PersistError::CorruptLog(13))));
+
+        //a plain filter checkpoints by itself
+        let plain_dir = dir.join("plain");
+        let mut persistent = PersistentFilter::open(&plain_dir, || Bloom1X::new(4, 4096, 64, 96)).unwrap().with_checkpoint_interval(64);
+        for i in 0..200u64 {
+            persistent.insert_u64(i).unwrap();
+        }
+        assert!(persistent.log_len() == 200 % 64);
+        let expected = persistent.into_inner().to_bytes();
+        let persistent = PersistentFilter::open(&plain_dir, || Bloom1X::new(4, 4096, 64, 96)).unwrap();
+        assert!(persistent.filter().to_bytes() == expected);
+
+        //a failed automatic checkpoint still keeps the change, in memory and on recovery
+        let mut persistent = persistent.with_checkpoint_interval(1);
+        std::fs::create_dir(plain_dir.join("snapshot.tmp")).unwrap();
+        assert!(persistent.insert_u64(1000).is_err());
+        assert!(persistent.contains_u64(1000) == 0x1);
+        std::fs::remove_dir(plain_dir.join("snapshot.tmp")).unwrap();
+        //so does one that wrote the snapshot but not the new log, the next insert starts that log
+        std::fs::create_dir(plain_dir.join("wal.tmp")).unwrap();
+        assert!(persistent.insert_u64(1001).is_err());
+        //while it cannot be started, changes fail before they are applied
+        assert!(persistent.insert_u64(1002).is_err());
+        assert!(persistent.contains_u64(1002) == 0x0);
+        std::fs::remove_dir(plain_dir.join("wal.tmp")).unwrap();
+        persistent.insert_u64(1003).unwrap();
+        let expected = persistent.into_inner().to_bytes();
+        let persistent = PersistentFilter::open(&plain_dir, || Bloom1X::new(4, 4096, 64, 96)).unwrap();
+        assert!(persistent.filter().to_bytes() == expected);
+        assert!([1000, 1001, 1003].iter().all(|&i| persistent.contains_u64(i) == 0x1));
+
+        //a checkpoint failing after its rename leaves the new snapshot, later changes go to a log of its generation
+        let mut persistent = persistent.with_checkpoint_interval(0);
+        crate::persist::FAIL_SYNC_DIR.set(true);
+        assert!(persistent.checkpoint().is_err());
+        persistent.insert_u32(1004).unwrap();
+        let expected = persistent.into_inner().to_bytes();
+        let persistent = PersistentFilter::open(&plain_dir, || Bloom1X::new(4, 4096, 64, 96)).unwrap();
+        assert!(persistent.filter().to_bytes() == expected);
+        assert!(persistent.contains_u32(1004) == 0x1 && persistent.log_len() == 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn python_bindings_match_rust() {
+        use pyo3::prelude::*;
+        use pyo3::types::{PyBytes, PyDict, PyList, PyModule};
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "bloom_1x").unwrap();
+            crate::python::bloom_1x(&module).unwrap();
+            let byte_keys: Vec<Vec<u8>> = (0..2000).map(|i| format!("key-{}", i).into_bytes()).collect();
+            let py_keys = PyList::new(py, byte_keys.iter().map(|key| PyBytes::new(py, key))).unwrap();
+
+            //a filter filled from Python is bit for bit the one filled through the Rust API
+            let filter = module.getattr("Bloom1X").unwrap().call1((4, 4096, 64, 96)).unwrap();
+            let mut bloom_filter = Bloom1X::new(4, 4096, 64, 96);
+            for i in 0..2000u64 {
+                let found = filter.call_method1("add", (i,)).unwrap().extract::<bool>().unwrap();
+                assert!(found == (bloom_filter.query_and_set_u64(i) == 1));
+            }
+            let found = filter.call_method1("add_many", (&py_keys,)).unwrap().extract::<usize>().unwrap();
+            let expected: usize = byte_keys.iter().map(|key| bloom_filter.query_and_set_bytes(key) as usize).sum();
+            assert!(found == expected);
+            let bytes = filter.call_method0("to_bytes").unwrap().extract::<Vec<u8>>().unwrap();
+            assert!(bytes == bloom_filter.to_bytes());
+
+            let options = PyDict::new(py);
+            options.set_item("counter_bits", 4).unwrap();
+            options.set_item("partitioned", true).unwrap();
+            let counter = module.getattr("Bloom1Counter").unwrap().call((4, 4096, 64, 96), Some(&options)).unwrap();
+            let mut counting_filter = Bloom1Counter::new(4, 4096, 64, 96).with_counter_width(CounterWidth::Bits4).with_partitioned_rows();
+            counter.call_method1("add_many", (&py_keys,)).unwrap();
+            for key in byte_keys.iter() {
+                counting_filter.query_and_inc_bytes(key);
+            }
+            for key in byte_keys.iter().take(500) {
+                counter.call_method1("remove", (PyBytes::new(py, key),)).unwrap();
+                counting_filter.try_remove_bytes(key).unwrap();
+            }
+            let bytes = counter.call_method0("to_bytes").unwrap().extract::<Vec<u8>>().unwrap();
+            assert!(bytes == counting_filter.to_bytes());
+        });
+    }
 }
@@ -0,0 +1,427 @@
+//! crash-consistent persistence of a filter: a write-ahead log of the digests of all inserts and
+//! removes, plus snapshots of the whole filter taken by checkpoint
+//!
+//! a directory holds two files:
+//! - snapshot: magic, generation, the filter bytes and their checksum, replaced atomically by rename
+//! - wal: magic, generation, then fixed size records (op, digest, checksum) appended before each change
+//!
+//! recovery loads the snapshot and replays the records of the log of the same generation;
+//! a log of an older generation was already folded into the snapshot by a checkpoint that crashed
+//! before resetting it, and an incomplete or damaged last record (a torn write) is cut off
+
+use crate::bloom::Bloom1X;
+use crate::bloom_counter::{Bloom1Counter, RemoveError};
+use crate::codec::{self, DecodeError, Reader};
+use crate::digest::{self, KeyDigest};
+use crate::filter::{DeletableFilter, MembershipFilter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"B1XP";
+const LOG_MAGIC: &[u8; 4] = b"B1XL";
+/// magic, version and generation
+const LOG_HEADER_SIZE: usize = 13;
+/// magic, version, generation, length of the filter bytes and their checksum
+const SNAPSHOT_OVERHEAD: usize = 25;
+/// op, the three digest words and the checksum
+const RECORD_SIZE: usize = 17;
+const OP_INSERT: u8 = 1;
+const OP_REMOVE: u8 = 2;
+
+/// reasons a persistent filter cannot be recovered or updated
+#[derive(Debug)]
+pub enum PersistError {
+    /// reading, writing or syncing a file failed
+    Io(io::Error),
+    /// the snapshot is damaged or does not hold the expected filter type
+    Snapshot(DecodeError),
+    /// a record before the end of the log is damaged, at the given byte offset of the log
+    CorruptLog(u64),
+    /// a failed append could not be cut off the log, the filter must be reopened before it is changed again
+    Poisoned
+}
+
+impl std::fmt::Display for PersistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistError::Io(error) => write!(f, "{}", error),
+            PersistError::Snapshot(error) => write!(f, "invalid snapshot: {}", error),
+            PersistError::CorruptLog(offset) => write!(f, "write-ahead log is corrupt at byte {}", offset),
+            PersistError::Poisoned => write!(f, "write-ahead log holds a change that was not applied, reopen the filter")
+        }
+    }
+}
+
+impl std::error::Error for PersistError {}
+
+impl From<io::Error> for PersistError {
+    fn from(error: io::Error) -> Self {
+        PersistError::Io(error)
+    }
+}
+
+/// filters that can be rebuilt from a snapshot and a replayed log
+pub trait Recoverable: MembershipFilter + Sized {
+    /// decodes the bytes written by MembershipFilter::to_bytes
+    fn from_snapshot(bytes: &[u8]) -> Result<Self, DecodeError>;
+    /// replays a logged removal, returns false if the filter type cannot remove keys
+    fn replay_remove(&mut self, digest: &KeyDigest) -> bool;
+}
+
+impl Recoverable for Bloom1X {
+    fn from_snapshot(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Bloom1X::from_bytes(bytes)
+    }
+
+    fn replay_remove(&mut self, _digest: &KeyDigest) -> bool {
+        false
+    }
+}
+
+impl Recoverable for Bloom1Counter {
+    fn from_snapshot(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Bloom1Counter::from_bytes(bytes)
+    }
+
+    fn replay_remove(&mut self, digest: &KeyDigest) -> bool {
+        //a remove is logged before it is tried, replaying it on the same state fails the same way
+        let _ = self.remove_digest(digest);
+        true
+    }
+}
+
+/// when appended records are forced to the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// every record is synced before the change is applied, nothing acknowledged is ever lost
+    EveryRecord,
+    /// records are only synced by sync and checkpoint, a crash can lose the changes since then
+    Manual
+}
+
+/// FNV-1a, enough to tell a torn record from a complete one
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+fn encode_record(op: u8, digest: &KeyDigest) -> [u8; RECORD_SIZE] {
+    let mut record = [0u8; RECORD_SIZE];
+    record[0] = op;
+    for (i, word) in digest.0.iter().enumerate() {
+        record[1 + 4 * i..5 + 4 * i].copy_from_slice(&word.to_le_bytes());
+    }
+    let sum = checksum(&record[..13]);
+    record[13..].copy_from_slice(&sum.to_le_bytes());
+    record
+}
+
+/// returns the op and the digest of a record, None if it is damaged
+fn decode_record(record: &[u8]) -> Option<(u8, KeyDigest)> {
+    let sum = u32::from_le_bytes(record[13..17].try_into().unwrap());
+    if sum != checksum(&record[..13]) || !(record[0] == OP_INSERT || record[0] == OP_REMOVE) {
+        return None;
+    }
+    let word = |i: usize| u32::from_le_bytes(record[1 + 4 * i..5 + 4 * i].try_into().unwrap());
+    Some((record[0], KeyDigest([word(0), word(1), word(2)])))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// makes the next directory sync of this thread fail
+    pub(crate) static FAIL_SYNC_DIR: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// syncs the directory so that a rename inside it survives a crash
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(test)]
+    if FAIL_SYNC_DIR.replace(false) {
+        return Err(io::Error::other("injected directory sync failure"));
+    }
+    File::open(dir)?.sync_all()
+}
+
+/// writes a file next to its final path and syncs it, returns the temporary path
+fn write_temporary(dir: &Path, name: &str, bytes: &[u8]) -> io::Result<PathBuf> {
+    let temporary = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(temporary)
+}
+
+/// writes a file next to its final path, syncs it and renames it over the old one
+fn replace_file(dir: &Path, name: &str, bytes: &[u8]) -> io::Result<()> {
+    fs::rename(write_temporary(dir, name, bytes)?, dir.join(name))?;
+    sync_dir(dir)
+}
+
+fn snapshot_bytes(generation: u64, filter: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(filter.len() + SNAPSHOT_OVERHEAD);
+    codec::put_header(&mut buffer, SNAPSHOT_MAGIC);
+    codec::put_u64(&mut buffer, generation);
+    codec::put_u64(&mut buffer, filter.len() as u64);
+    buffer.extend_from_slice(filter);
+    codec::put_u32(&mut buffer, checksum(filter));
+    buffer
+}
+
+/// returns the generation and the filter bytes of a snapshot
+fn decode_snapshot(bytes: &[u8]) -> Result<(u64, &[u8]), DecodeError> {
+    let mut reader = Reader::new(bytes);
+    reader.header(SNAPSHOT_MAGIC)?;
+    let generation = reader.u64()?;
+    let len = usize::try_from(reader.u64()?).map_err(|_| DecodeError::Truncated)?;
+    let filter = reader.take(len)?;
+    if reader.u32()? != checksum(filter) {
+        return Err(DecodeError::InvalidParameters);
+    }
+    reader.finish()?;
+    Ok((generation, filter))
+}
+
+/// a filter whose changes are logged to a directory before being applied
+#[derive(Debug)]
+pub struct PersistentFilter<F: Recoverable> {
+    filter: F,
+    dir: PathBuf,
+    log: File,
+    /// generation of the snapshot the log applies to
+    generation: u64,
+    /// generation in the header of the open log, behind generation when a checkpoint could not start its log
+    log_generation: u64,
+    /// number of records in the log
+    records: u64,
+    /// set when a failed append could not be cut off the log
+    poisoned: bool,
+    sync_mode: SyncMode,
+    /// number of records after which a checkpoint is taken on its own, 0 for never
+    checkpoint_interval: u64
+}
+
+impl<F: Recoverable> PersistentFilter<F> {
+    /// recovers the filter stored in dir, or stores the one built by initial if dir holds none
+    /// a torn last record of the log is cut off, a damaged record before it is an error
+    pub fn open(dir: impl AsRef<Path>, initial: impl FnOnce() -> F) -> Result<Self, PersistError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (mut filter, generation) = match fs::read(dir.join("snapshot")) {
+            Ok(bytes) => {
+                let (generation, filter) = decode_snapshot(&bytes).map_err(PersistError::Snapshot)?;
+                (F::from_snapshot(filter).map_err(PersistError::Snapshot)?, generation)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let filter = initial();
+                replace_file(&dir, "snapshot", &snapshot_bytes(0, &filter.to_bytes()))?;
+                (filter, 0)
+            }
+            Err(error) => return Err(error.into())
+        };
+
+        let log_bytes = match fs::read(dir.join("wal")) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into())
+        };
+        let mut reader = Reader::new(&log_bytes);
+        let log_generation = match reader.header(LOG_MAGIC).and_then(|_| reader.u64()) {
+            Ok(log_generation) => Some(log_generation),
+            //a crash while creating the log leaves it without a full header, it holds no record yet
+            Err(DecodeError::Truncated) => None,
+            Err(_) => return Err(PersistError::CorruptLog(0))
+        };
+        if log_generation.is_some_and(|log_generation| log_generation > generation) {
+            return Err(PersistError::CorruptLog(0));
+        }
+
+        let mut records = 0;
+        if log_generation == Some(generation) {
+            let body = &log_bytes[LOG_HEADER_SIZE..];
+            let complete = body.len() / RECORD_SIZE;
+            for (i, record) in body.chunks(RECORD_SIZE).enumerate() {
+                let offset = (LOG_HEADER_SIZE + i * RECORD_SIZE) as u64;
+                let decoded = if record.len() == RECORD_SIZE { decode_record(record) } else { None };
+                match decoded {
+                    Some((OP_INSERT, digest)) => {
+                        filter.insert_digest(&digest);
+                    }
+                    Some((_, digest)) => {
+                        if !filter.replay_remove(&digest) {
+                            return Err(PersistError::CorruptLog(offset));
+                        }
+                    }
+                    //only the last record can be torn
+                    None if i + 1 >= complete => break,
+                    None => return Err(PersistError::CorruptLog(offset))
+                }
+                records += 1;
+            }
+            let log = OpenOptions::new().write(true).open(dir.join("wal"))?;
+            log.set_len(LOG_HEADER_SIZE as u64 + records * RECORD_SIZE as u64)?;
+            log.sync_all()?;
+        } else {
+            Self::reset_log(&dir, generation)?;
+        }
+
+        let log = OpenOptions::new().append(true).open(dir.join("wal"))?;
+        Ok(Self { filter, dir, log, generation, log_generation: generation, records, poisoned: false, sync_mode: SyncMode::EveryRecord, checkpoint_interval: 0 })
+    }
+
+    /// changes when appended records are synced, EveryRecord by default
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// takes a checkpoint every time the log reaches the given number of records
+    pub fn with_checkpoint_interval(mut self, records: u64) -> Self {
+        self.checkpoint_interval = records;
+        self
+    }
+
+    fn reset_log(dir: &Path, generation: u64) -> io::Result<()> {
+        let mut header = Vec::with_capacity(LOG_HEADER_SIZE);
+        codec::put_header(&mut header, LOG_MAGIC);
+        codec::put_u64(&mut header, generation);
+        replace_file(dir, "wal", &header)
+    }
+
+    /// the recovered filter with all logged changes applied
+    pub fn filter(&self) -> &F {
+        &self.filter
+    }
+
+    /// number of records logged since the last checkpoint
+    pub fn log_len(&self) -> u64 {
+        self.records
+    }
+
+    /// replaces the log by an empty one for the current generation
+    fn start_log(&mut self) -> Result<(), PersistError> {
+        Self::reset_log(&self.dir, self.generation)?;
+        self.log = OpenOptions::new().append(true).open(self.dir.join("wal"))?;
+        self.log_generation = self.generation;
+        Ok(())
+    }
+
+    fn append(&mut self, op: u8, digest: &KeyDigest) -> Result<(), PersistError> {
+        if self.poisoned {
+            return Err(PersistError::Poisoned);
+        }
+        //records appended to the log of an older snapshot would be ignored by recovery
+        if self.log_generation != self.generation {
+            self.start_log()?;
+        }
+        let written = self.log.write_all(&encode_record(op, digest)).and_then(|_| match self.sync_mode {
+            SyncMode::EveryRecord => self.log.sync_data(),
+            SyncMode::Manual => Ok(())
+        });
+        if let Err(error) = written {
+            //cut off what made it into the log, the change is not applied; the log is in append mode,
+            //so the next record starts at the new end
+            if let Err(truncation) = self.log.set_len(LOG_HEADER_SIZE as u64 + self.records * RECORD_SIZE as u64) {
+                //recovery would replay a change the filter never saw
+                self.poisoned = true;
+                return Err(io::Error::other(format!("{error}, and the log could not be cut back: {truncation}")).into());
+            }
+            return Err(error.into());
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    fn after_append(&mut self) -> Result<(), PersistError> {
+        if self.checkpoint_interval > 0 && self.records >= self.checkpoint_interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// logs and inserts the key behind a digest, returns 0x1 if it was already present
+    /// an Err from the automatic checkpoint comes after the insert was logged and applied, recovery keeps it
+    pub fn insert_digest(&mut self, digest: &KeyDigest) -> Result<u8, PersistError> {
+        self.append(OP_INSERT, digest)?;
+        let result = self.filter.insert_digest(digest);
+        self.after_append()?;
+        Ok(result)
+    }
+
+    pub fn insert_bytes(&mut self, bytes: &[u8]) -> Result<u8, PersistError> {
+        self.insert_digest(&digest::hash_bytes(bytes))
+    }
+
+    pub fn insert_u32(&mut self, bytes: u32) -> Result<u8, PersistError> {
+        self.insert_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn insert_u64(&mut self, bytes: u64) -> Result<u8, PersistError> {
+        self.insert_digest(&digest::hash_u64(bytes))
+    }
+
+    pub fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        self.filter.contains_digest(digest)
+    }
+
+    pub fn contains_bytes(&self, bytes: &[u8]) -> u8 {
+        self.filter.contains_bytes(bytes)
+    }
+
+    pub fn contains_u32(&self, bytes: u32) -> u8 {
+        self.filter.contains_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn contains_u64(&self, bytes: u64) -> u8 {
+        self.filter.contains_u64(bytes)
+    }
+
+    /// forces the records appended so far to the disk
+    pub fn sync(&mut self) -> Result<(), PersistError> {
+        Ok(self.log.sync_data()?)
+    }
+
+    /// writes a snapshot of the filter and starts an empty log for it
+    /// a crash in between leaves the new snapshot with the old log, which recovery then ignores
+    /// if only the snapshot could be written, the next change starts the log first
+    pub fn checkpoint(&mut self) -> Result<(), PersistError> {
+        if self.poisoned {
+            return Err(PersistError::Poisoned);
+        }
+        let generation = self.generation + 1;
+        let temporary = write_temporary(&self.dir, "snapshot", &snapshot_bytes(generation, &self.filter.to_bytes()))?;
+        fs::rename(temporary, self.dir.join("snapshot"))?;
+        //from the rename on, recovery ignores the old log, whatever fails next
+        self.generation = generation;
+        self.records = 0;
+        sync_dir(&self.dir)?;
+        self.start_log()
+    }
+
+    /// gives back the filter, the directory keeps its state
+    pub fn into_inner(self) -> F {
+        self.filter
+    }
+}
+
+impl<F: Recoverable + DeletableFilter> PersistentFilter<F> {
+    /// logs and removes the key behind a digest
+    /// the log is written first, so a failed remove is logged too and fails again on replay;
+    /// an Err from the automatic checkpoint comes after the remove was logged and applied, recovery keeps it
+    pub fn remove_digest(&mut self, digest: &KeyDigest) -> Result<Result<(), RemoveError>, PersistError> {
+        self.append(OP_REMOVE, digest)?;
+        let result = self.filter.remove_digest(digest);
+        self.after_append()?;
+        Ok(result)
+    }
+
+    pub fn remove_bytes(&mut self, bytes: &[u8]) -> Result<Result<(), RemoveError>, PersistError> {
+        self.remove_digest(&digest::hash_bytes(bytes))
+    }
+
+    pub fn remove_u32(&mut self, bytes: u32) -> Result<Result<(), RemoveError>, PersistError> {
+        self.remove_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn remove_u64(&mut self, bytes: u64) -> Result<Result<(), RemoveError>, PersistError> {
+        self.remove_digest(&digest::hash_u64(bytes))
+    }
+}
@@ -0,0 +1,248 @@
+//! Python bindings, built with the `python` feature (e.g. through maturin)
+//! every call goes through the Rust API, so a filter built from Python is bit for bit
+//! the one the same keys would give in Rust and the saved files are interchangeable
+
+use crate::bloom::Bloom1X;
+use crate::bloom_counter::{Bloom1Counter, CounterWidth};
+use crate::digest::{self, KeyDigest};
+use crate::index::{self, IndexMode};
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+/// a Python key copied out of the interpreter: bytes of at most 48 bytes, or an int hashed as a u64
+enum Key {
+    Bytes(Vec<u8>),
+    U64(u64)
+}
+
+impl Key {
+    fn extract(key: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(bytes) = key.cast::<PyBytes>() {
+            let bytes = bytes.as_bytes();
+            if bytes.len() > 48 {
+                return Err(PyValueError::new_err("keys are limited to 48 bytes"));
+            }
+            return Ok(Key::Bytes(bytes.to_vec()));
+        }
+        Ok(Key::U64(key.extract::<u64>()?))
+    }
+
+    fn digest(&self) -> KeyDigest {
+        match self {
+            Key::Bytes(bytes) => digest::hash_bytes(bytes),
+            Key::U64(key) => digest::hash_u64(*key)
+        }
+    }
+}
+
+fn key_digest(key: &Bound<'_, PyAny>) -> PyResult<KeyDigest> {
+    Ok(Key::extract(key)?.digest())
+}
+
+/// copies a batch given either as a numpy uint64 array or as any iterable of keys,
+/// so that it can be hashed without holding the GIL
+fn batch_keys(keys: &Bound<'_, PyAny>) -> PyResult<Vec<Key>> {
+    //only arrays go through numpy, so plain lists work without it
+    if keys.hasattr("dtype")? {
+        let array = keys.extract::<PyReadonlyArray1<u64>>()?;
+        return Ok(array.as_array().iter().map(|&key| Key::U64(key)).collect());
+    }
+    keys.try_iter()?.map(|key| Key::extract(&key?)).collect()
+}
+
+fn index_mode(name: &str) -> PyResult<IndexMode> {
+    match name {
+        "modulo" => Ok(IndexMode::Modulo),
+        "multiply_shift" => Ok(IndexMode::MultiplyShift),
+        _ => Err(PyValueError::new_err("index_mode must be 'modulo' or 'multiply_shift'"))
+    }
+}
+
+fn check_geometry(k: usize, l: usize, w: usize, h: usize, partitioned: bool) -> PyResult<()> {
+    if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+        return Err(PyValueError::new_err("invalid filter geometry"));
+    }
+    Ok(())
+}
+
+#[pyclass(name = "Bloom1X", module = "bloom_1x")]
+pub struct PyBloom1X {
+    inner: Bloom1X
+}
+
+#[pymethods]
+impl PyBloom1X {
+    #[new]
+    #[pyo3(signature = (k, l, w, h, index_mode = "modulo", partitioned = false))]
+    fn new(k: usize, l: usize, w: usize, h: usize, index_mode: &str, partitioned: bool) -> PyResult<Self> {
+        check_geometry(k, l, w, h, partitioned)?;
+        let mut inner = Bloom1X::new(k, l, w, h).with_index_mode(self::index_mode(index_mode)?);
+        if partitioned {
+            inner = inner.with_partitioned_rows();
+        }
+        Ok(Self { inner })
+    }
+
+    /// inserts a key, returns True if it was already present
+    fn add(&mut self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.inner.insert_digest(&key_digest(key)?) == 1)
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.inner.contains_digest(&key_digest(key)?) == 1)
+    }
+
+    /// inserts a numpy uint64 array or a list of keys, returns how many were already present
+    fn add_many(&mut self, py: Python<'_>, keys: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let keys = batch_keys(keys)?;
+        let inner = &mut self.inner;
+        Ok(py.detach(|| keys.iter().map(|key| inner.insert_digest(&key.digest()) as usize).sum()))
+    }
+
+    /// membership of a numpy uint64 array or a list of keys, as a numpy bool array
+    fn contains_many<'py>(&self, py: Python<'py>, keys: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyArray1<bool>>> {
+        let keys = batch_keys(keys)?;
+        let inner = &self.inner;
+        let result: Vec<bool> = py.detach(|| keys.iter().map(|key| inner.contains_digest(&key.digest()) == 1).collect());
+        Ok(PyArray1::from_vec(py, result))
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    /// geometry, memory and fill of the filter
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = PyDict::new(py);
+        stats.set_item("k", self.inner.k())?;
+        stats.set_item("l", self.inner.l())?;
+        stats.set_item("w", self.inner.w())?;
+        stats.set_item("h", self.inner.h())?;
+        stats.set_item("memory_size", self.inner.memory_size())?;
+        stats.set_item("count_ones", self.inner.count_ones())?;
+        stats.set_item("fill_ratio", self.inner.count_ones() as f64 / (self.inner.l() * self.inner.w()) as f64)?;
+        Ok(stats)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.to_bytes())
+    }
+
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        let inner = Bloom1X::from_bytes(bytes).map_err(|error| PyValueError::new_err(error.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    fn save(&self, path: std::path::PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.inner.to_bytes())?)
+    }
+
+    #[staticmethod]
+    fn load(path: std::path::PathBuf) -> PyResult<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[pyclass(name = "Bloom1Counter", module = "bloom_1x")]
+pub struct PyBloom1Counter {
+    inner: Bloom1Counter
+}
+
+#[pymethods]
+impl PyBloom1Counter {
+    #[new]
+    #[pyo3(signature = (k, l, w, h, counter_bits = 8, index_mode = "modulo", partitioned = false))]
+    fn new(k: usize, l: usize, w: usize, h: usize, counter_bits: usize, index_mode: &str, partitioned: bool) -> PyResult<Self> {
+        check_geometry(k, l, w, h, partitioned)?;
+        let width = CounterWidth::from_bits(counter_bits).ok_or_else(|| PyValueError::new_err("counter_bits must be 2, 4, 8 or 16"))?;
+        let mut inner = Bloom1Counter::new(k, l, w, h).with_index_mode(self::index_mode(index_mode)?).with_counter_width(width);
+        if partitioned {
+            inner = inner.with_partitioned_rows();
+        }
+        Ok(Self { inner })
+    }
+
+    /// inserts a key, returns True if it was already present
+    fn add(&mut self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.inner.insert_digest(&key_digest(key)?).and_result == 1)
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.inner.contains_digest(&key_digest(key)?) == 1)
+    }
+
+    /// removes a key inserted before, raises KeyError if the filter shows it was never inserted
+    fn remove(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        let query_result = self.inner.parse_hash(&key_digest(key)?.0);
+        match self.inner.remove_by_result(&query_result) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(PyKeyError::new_err(error.to_string()))
+        }
+    }
+
+    /// upper bound of the number of times a key was inserted
+    fn count(&self, key: &Bound<'_, PyAny>) -> PyResult<u64> {
+        let query_result = self.inner.parse_hash(&key_digest(key)?.0);
+        Ok(self.inner.min_count(&query_result))
+    }
+
+    /// inserts a numpy uint64 array or a list of keys, returns how many were already present
+    fn add_many(&mut self, py: Python<'_>, keys: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let keys = batch_keys(keys)?;
+        let inner = &mut self.inner;
+        Ok(py.detach(|| keys.iter().map(|key| inner.insert_digest(&key.digest()).and_result as usize).sum()))
+    }
+
+    /// membership of a numpy uint64 array or a list of keys, as a numpy bool array
+    fn contains_many<'py>(&self, py: Python<'py>, keys: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyArray1<bool>>> {
+        let keys = batch_keys(keys)?;
+        let inner = &self.inner;
+        let result: Vec<bool> = py.detach(|| keys.iter().map(|key| inner.contains_digest(&key.digest()) == 1).collect());
+        Ok(PyArray1::from_vec(py, result))
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    /// geometry and memory of the filter
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = PyDict::new(py);
+        stats.set_item("k", self.inner.k())?;
+        stats.set_item("l", self.inner.l())?;
+        stats.set_item("w", self.inner.w())?;
+        stats.set_item("h", self.inner.h())?;
+        stats.set_item("counter_bits", self.inner.counter_width().bits())?;
+        stats.set_item("memory_size", self.inner.memory_size())?;
+        Ok(stats)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.to_bytes())
+    }
+
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        let inner = Bloom1Counter::from_bytes(bytes).map_err(|error| PyValueError::new_err(error.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    fn save(&self, path: std::path::PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.inner.to_bytes())?)
+    }
+
+    #[staticmethod]
+    fn load(path: std::path::PathBuf) -> PyResult<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[pymodule]
+pub(crate) fn bloom_1x(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyBloom1X>()?;
+    module.add_class::<PyBloom1Counter>()?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! retouched Bloom-1 filter (Donnet et al., 2006)
+//! a key confirmed to be a false positive is removed from a Bloom1X by clearing one of its bits,
+//! at the risk of turning inserted keys using that bit into false negatives
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::bloom::{Bloom1X, QueryResult};
+
+/// selects which bit of a false positive is cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetouchPolicy {
+    /// one of the k bits picked pseudo-randomly from the seed and the key position
+    Random(u64),
+    /// the bit used by the fewest protected keys, the first bit when no protected keys are given
+    LeastShared,
+    /// the bit set the longest time ago, requires a filter built with recency hints
+    /// a retouch with this policy on a filter without them changes nothing and returns None
+    LeastRecentlySet
+}
+
+/// describes the bit cleared by a retouch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetouchReport {
+    /// row of the cleared bit
+    pub row_index: usize,
+    /// position of the cleared bit inside its row
+    pub bit_index: usize,
+    /// protected keys that were reported present before the retouch and are not anymore
+    /// None when no protected keys were given
+    pub false_negatives: Option<usize>
+}
+
+impl Bloom1X {
+    /// clears one bit of a key reported present, chosen by the policy
+    /// protected keys are given through their positions, as returned by the search functions
+    /// returns None if the key is not reported present or if the policy needs recency hints
+    /// the filter does not keep, in which case nothing is changed
+    pub fn retouch_by_result(&mut self, qr: &QueryResult, policy: RetouchPolicy, protected: Option<&[QueryResult]>) -> Option<RetouchReport> {
+        if self.query_by_result(qr) == 0 {
+            return None;
+        }
+        let row_index = qr.row_index;
+        let bit_index = match policy {
+            RetouchPolicy::Random(seed) => {
+                let mut z = seed ^ (row_index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                for &bit_index in qr.bit_indexes.iter() {
+                    z = (z ^ bit_index as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z ^= z >> 31;
+                }
+                qr.bit_indexes[(z % qr.bit_indexes.len() as u64) as usize]
+            }
+            RetouchPolicy::LeastShared => {
+                let shared = |bit_index: usize| {
+                    protected.map_or(0, |protected| {
+                        protected.iter().filter(|key| key.row_index == row_index && key.bit_indexes.contains(&bit_index)).count()
+                    })
+                };
+                *qr.bit_indexes.iter().min_by_key(|&&bit_index| shared(bit_index)).unwrap()
+            }
+            RetouchPolicy::LeastRecentlySet => {
+                let recency = self.recency.as_ref()?;
+                let w = self.w();
+                //stamps wrap around, so compare their age relative to the last insertion
+                let age = |bit_index: usize| self.insertions.wrapping_sub(recency[row_index * w + bit_index]);
+                *qr.bit_indexes.iter().max_by_key(|&&bit_index| age(bit_index)).unwrap()
+            }
+        };
+
+        //protected keys that currently pass and depend on the bit
+        let false_negatives = protected.map(|protected| {
+            protected
+                .iter()
+                .filter(|key| key.row_index == row_index && key.bit_indexes.contains(&bit_index))
+                .filter(|key| self.query_by_result(key) == 1)
+                .count()
+        });
+        self.filter[row_index][bit_index / 8] &= !(1 << (bit_index % 8));
+        Some(RetouchReport { row_index, bit_index, false_negatives })
+    }
+
+    /// these functions remove a confirmed false positive from the filter, see retouch_by_result
+    pub fn retouch_bytes(&mut self, bytes: &[u8], policy: RetouchPolicy, protected: Option<&[QueryResult]>) -> Option<RetouchReport> {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let query_result = self.locate(&hasher.digest_nc());
+        self.retouch_by_result(&query_result, policy, protected)
+    }
+
+    pub fn retouch_u32(&mut self, bytes: u32, policy: RetouchPolicy, protected: Option<&[QueryResult]>) -> Option<RetouchReport> {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let query_result = self.locate(&hasher.digest_nc());
+        self.retouch_by_result(&query_result, policy, protected)
+    }
+
+    pub fn retouch_u64(&mut self, bytes: u64, policy: RetouchPolicy, protected: Option<&[QueryResult]>) -> Option<RetouchReport> {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let query_result = self.locate(&hasher.digest_nc());
+        self.retouch_by_result(&query_result, policy, protected)
+    }
+}
@@ -0,0 +1,107 @@
+//! time-windowed filter made of G generations of Bloom1X sharing the same geometry
+//! a key is seen if any generation holds it, inserts go to the current generation
+//! and rotate() recycles the oldest generation as the new current one
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::bloom::{Bloom1X, QueryResult};
+
+#[derive(Debug)]
+pub struct RotatingBloom1X {
+    /// generations of the filter, all with the same geometry
+    generations: Vec<Bloom1X>,
+    /// index of the generation receiving the inserts
+    current: usize
+}
+
+impl RotatingBloom1X {
+    /// g -> number of generations, the other parameters are the ones of Bloom1X::new
+    pub fn new(k: usize, l: usize, w: usize, h: usize, g: usize) -> Self {
+        assert!(g >= 2);
+        let generations = (0..g).map(|_| Bloom1X::new(k, l, w, h)).collect();
+        Self { generations, current: 0 }
+    }
+
+    /// number of generations
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+
+    pub fn clear(&mut self) {
+        for generation in self.generations.iter_mut() {
+            generation.clear();
+        }
+    }
+
+    /// clears the oldest generation and makes it the current one, reusing its memory
+    pub fn rotate(&mut self) {
+        self.current = (self.current + 1) % self.generations.len();
+        self.generations[self.current].clear();
+    }
+
+    /// ORs the membership of a located key over all generations
+    #[inline(always)]
+    fn query_located(&self, qr: &QueryResult) -> u8 {
+        let mut or_result = 0;
+        for generation in self.generations.iter() {
+            or_result |= generation.query_by_result(qr);
+        }
+        or_result
+    }
+
+    /// the query functions check if a provided key is member of any generation
+    /// the key is hashed once and its position reused for every generation
+    pub fn query_bytes(&self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.query_located(&self.generations[0].locate(&digest))
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.query_located(&self.generations[0].locate(&digest))
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        self.query_located(&self.generations[0].locate(&digest))
+    }
+
+    /// these functions query the membership over all generations
+    /// and insert the key in the current generation
+    pub fn query_and_set_bytes(&mut self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.generations[0].locate(&digest);
+        let result = self.query_located(&query_result);
+        self.generations[self.current].update_filter(&query_result);
+        result
+    }
+
+    pub fn query_and_set_u32(&mut self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.generations[0].locate(&digest);
+        let result = self.query_located(&query_result);
+        self.generations[self.current].update_filter(&query_result);
+        result
+    }
+
+    pub fn query_and_set_u64(&mut self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.generations[0].locate(&digest);
+        let result = self.query_located(&query_result);
+        self.generations[self.current].update_filter(&query_result);
+        result
+    }
+}
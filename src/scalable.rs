@@ -0,0 +1,255 @@
+//! scalable Bloom-1 filter chaining Bloom1X stages (Almeida et al., 2007)
+//! stage i is closed once its false positive rate reaches f^(k + i), f being the fill threshold
+//! and k the hashes of the first stage, so the total stays below f^k / (1 - f)
+//! the rate of a stage is tracked row by row as the mean of (row fill)^k_i, which accounts for
+//! the uneven load of the rows of a Bloom-1 filter
+//! every new stage has `growth` times more rows and one more hash per key, which keeps the stages
+//! closing near the fill f; once the sub-hashes run out of bits k stops growing and later stages
+//! close at a lower fill instead, and once a Bloom1X cannot address more rows l stops growing
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::bloom::Bloom1X;
+use crate::codec::{self, DecodeError, Reader};
+use crate::index::IndexMode;
+
+/// magic starting the encoding of a ScalableBloom1X
+const MAGIC: &[u8; 4] = b"B1XS";
+
+#[derive(Debug)]
+pub struct ScalableBloom1X {
+    /// stages of the filter, the last one receiving the inserts
+    stages: Vec<Bloom1X>,
+    /// sum over the rows of every stage of (row fill)^k, the chance that a key absent from the row matches it
+    row_rates: Vec<f64>,
+    /// number of hash bits available per key
+    h: usize,
+    /// fill of the first stage after which a new stage is added, also the ratio between the rates of consecutive stages
+    fill_threshold: f64,
+    /// row count multiplier between consecutive stages
+    growth: usize
+}
+
+impl ScalableBloom1X {
+    /// parameters of the first stage, l must be a power of two
+    /// h -> size of hash function output in bits, stage hashes are chosen to fit in it
+    pub fn new(k: usize, l: usize, w: usize, h: usize) -> Self {
+        assert!(l.is_power_of_two());
+        let mut scalable = Self { stages: vec![], row_rates: vec![], h, fill_threshold: 0.5, growth: 2 };
+        scalable.add_stage(k, l, w);
+        scalable
+    }
+
+    /// fill of the first stage after which a new stage is added, 0.5 by default
+    pub fn with_fill_threshold(mut self, fill_threshold: f64) -> Self {
+        assert!(fill_threshold > 0.0 && fill_threshold < 1.0);
+        self.fill_threshold = fill_threshold;
+        self
+    }
+
+    /// row count multiplier between consecutive stages, 2 by default
+    pub fn with_growth(mut self, growth: usize) -> Self {
+        assert!(growth.is_power_of_two() && growth >= 2);
+        self.growth = growth;
+        self
+    }
+
+    /// hash bits of a stage, only as many as split evenly between the rows and the k sub-hashes
+    fn stage_h(&self, k: usize, l: usize) -> usize {
+        let row_bits = l.ilog2() as usize;
+        row_bits + k * ((self.h - row_bits) / k)
+    }
+
+    /// appends an empty stage
+    fn add_stage(&mut self, k: usize, l: usize, w: usize) {
+        assert!((l.ilog2() as usize) < self.h);
+        self.stages.push(Bloom1X::new(k, l, w, self.stage_h(k, l)));
+        self.row_rates.push(0.0);
+    }
+
+    /// k and l of the stage following a stage of the given geometry
+    fn next_stage(&self, k: usize, l: usize, w: usize) -> (usize, usize) {
+        //stop adding rows at the largest row count a Bloom1X can address
+        let l = match l.checked_mul(self.growth) {
+            Some(grown) if grown < u32::MAX as usize && (grown.ilog2() as usize) < self.h => grown,
+            _ => l
+        };
+        //stop adding hashes once a sub-hash could not address a whole row anymore
+        let row_bits = l.ilog2() as usize;
+        let position_bits = (usize::BITS - (w - 1).leading_zeros()) as usize;
+        let k = if (self.h - row_bits) / (k + 1) >= position_bits { k + 1 } else { k };
+        (k, l)
+    }
+
+    /// false positive rate at which a stage is closed, f^(k + i)
+    fn stage_target(&self, stage: usize) -> f64 {
+        f64::powf(self.fill_threshold, (self.stages[0].k() + stage) as f64)
+    }
+
+    /// chance that a key absent from a row with the given number of set bits matches it
+    fn row_rate(stage: &Bloom1X, ones: usize) -> f64 {
+        f64::powf(ones as f64 / stage.w() as f64, stage.k() as f64)
+    }
+
+    /// bound of the false positive rate over all stages, f^k / (1 - f) for the fill threshold f
+    /// and the k of the first stage
+    pub fn target_false_positive_rate(&self) -> f64 {
+        f64::powf(self.fill_threshold, self.stages[0].k() as f64) / (1.0 - self.fill_threshold)
+    }
+
+    /// adds the next stage when the current one is full
+    fn grow_if_full(&mut self) {
+        let stage = self.stages.len() - 1;
+        let current = &self.stages[stage];
+        if self.row_rates[stage] < self.stage_target(stage) * current.l() as f64 {
+            return;
+        }
+        let w = current.w();
+        let (k, l) = self.next_stage(current.k(), current.l(), w);
+        self.add_stage(k, l, w);
+    }
+
+    /// stages of the filter, oldest first
+    pub fn stages(&self) -> &[Bloom1X] {
+        &self.stages
+    }
+
+    /// number of bytes used by the bits of all stages
+    pub fn memory_size(&self) -> usize {
+        self.stages.iter().map(|stage| stage.memory_size()).sum()
+    }
+
+    /// false positive rate given the current fill of every row of every stage
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let mut true_negative = 1.0;
+        for (stage, &row_rates) in self.stages.iter().zip(self.row_rates.iter()) {
+            true_negative *= 1.0 - row_rates / stage.l() as f64;
+        }
+        1.0 - true_negative
+    }
+
+    /// drops all stages but the first one and clears it
+    pub fn clear(&mut self) {
+        self.stages.truncate(1);
+        self.row_rates.truncate(1);
+        self.stages[0].clear();
+        self.row_rates[0] = 0.0;
+    }
+
+    /// checks all stages for a digest computed once
+    #[inline(always)]
+    fn query_digest(&self, digest: &[u32; 3]) -> u8 {
+        let mut or_result = 0;
+        for stage in self.stages.iter() {
+            or_result |= stage.query_by_result(&stage.locate(digest));
+        }
+        or_result
+    }
+
+    /// inserts a digest in the current stage unless some stage already holds it
+    #[inline(always)]
+    fn insert_digest(&mut self, digest: &[u32; 3]) -> u8 {
+        if self.query_digest(digest) == 1 {
+            return 1;
+        }
+        let stage = self.stages.last_mut().unwrap();
+        let qr = stage.locate(digest);
+        let row = &stage.filter[qr.row_index];
+        let ones = row.iter().map(|byte| byte.count_ones() as usize).sum::<usize>();
+        let bits_set = stage.update_filter(&qr);
+        let rate = Self::row_rate(stage, ones + bits_set) - Self::row_rate(stage, ones);
+        *self.row_rates.last_mut().unwrap() += rate;
+        self.grow_if_full();
+        0
+    }
+
+    /// the query functions check if a provided key is member of any stage
+    /// returns 0x1 if the provided bytes were found inside the filter and 0x0 otherwise
+    pub fn query_bytes(&self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        self.query_digest(&hasher.digest_nc())
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        self.query_digest(&hasher.digest_nc())
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        self.query_digest(&hasher.digest_nc())
+    }
+
+    /// these functions query the membership over all stages
+    /// and insert the key in the current stage if it was not found
+    pub fn query_and_set_bytes(&mut self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        self.insert_digest(&hasher.digest_nc())
+    }
+
+    pub fn query_and_set_u32(&mut self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        self.insert_digest(&hasher.digest_nc())
+    }
+
+    pub fn query_and_set_u64(&mut self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        self.insert_digest(&hasher.digest_nc())
+    }
+
+    /// serializes the settings of the filter followed by its stage list
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        codec::put_header(&mut buffer, MAGIC);
+        codec::put_u32(&mut buffer, self.h as u32);
+        codec::put_f64(&mut buffer, self.fill_threshold);
+        codec::put_u32(&mut buffer, self.growth as u32);
+        codec::put_u32(&mut buffer, self.stages.len() as u32);
+        for stage in self.stages.iter() {
+            stage.encode(&mut buffer);
+        }
+        buffer
+    }
+
+    /// rebuilds a filter serialized by to_bytes
+    /// every stage must have the geometry the filter would have given it, otherwise later inserts could not grow it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(MAGIC)?;
+        let h = reader.u32()? as usize;
+        let fill_threshold = reader.f64()?;
+        let growth = reader.u32()? as usize;
+        let count = reader.u32()? as usize;
+        let valid = fill_threshold > 0.0 && fill_threshold < 1.0 && growth.is_power_of_two() && growth >= 2 && count > 0;
+        if !valid {
+            return Err(DecodeError::InvalidParameters);
+        }
+        let mut scalable = Self { stages: vec![], row_rates: vec![], h, fill_threshold, growth };
+        for _ in 0..count {
+            let stage = Bloom1X::decode(&mut reader)?;
+            let (k, l) = match scalable.stages.last() {
+                Some(previous) if stage.w() == previous.w() => scalable.next_stage(previous.k(), previous.l(), previous.w()),
+                Some(_) => return Err(DecodeError::InvalidParameters),
+                None if stage.l().is_power_of_two() && (stage.l().ilog2() as usize) < h => (stage.k(), stage.l()),
+                None => return Err(DecodeError::InvalidParameters)
+            };
+            let default = stage.index_mode() == IndexMode::default() && !stage.partitioned();
+            if (stage.k(), stage.l()) != (k, l) || stage.h() != scalable.stage_h(k, l) || !default {
+                return Err(DecodeError::InvalidParameters);
+            }
+            scalable.row_rates.push((0..stage.l()).map(|i| {
+                Self::row_rate(&stage, stage.row(i).iter().map(|byte| byte.count_ones() as usize).sum())
+            }).sum());
+            scalable.stages.push(stage);
+        }
+        reader.finish()?;
+        Ok(scalable)
+    }
+}
@@ -0,0 +1,341 @@
+//! Bloom1X living in a MAP_SHARED file, built with the `shared` feature
+//! every process that maps the file works on the same bits through atomic byte operations,
+//! so inserts are visible to all of them at once; files under /dev/shm stay in memory (see shm_path)
+//!
+//! layout: a 64 byte header (magic, version, k, l, w, h, index mode, partitioning) followed by
+//! the l rows of w/8 bytes, laid out like the rows of a Bloom1X
+//! the magic is written last by the creator, so a process attaching too early sees BadMagic instead of a partial filter
+
+use crate::bloom::Bloom1X;
+use crate::codec::DecodeError;
+use crate::digest::{self, KeyDigest};
+use crate::filter::{FilterParameters, MembershipFilter};
+use crate::index::{self, IndexMode};
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+
+/// magic starting a shared filter file
+const MAGIC: &[u8; 4] = b"B1XM";
+/// version of the shared layout, bumped on any incompatible change
+const VERSION: u8 = 1;
+/// size of the header, keeping the rows 64 byte aligned
+const HEADER_SIZE: usize = 64;
+
+/// reasons a shared filter cannot be attached
+#[derive(Debug)]
+pub enum AttachError {
+    /// the file could not be opened or mapped
+    Io(io::Error),
+    /// the header or the size of the file does not describe a valid filter
+    Invalid(DecodeError),
+    /// the file holds a valid filter of another geometry, index mode or partitioning than the expected one,
+    /// which it reports
+    GeometryMismatch(FilterParameters, IndexMode, bool)
+}
+
+impl std::fmt::Display for AttachError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttachError::Io(error) => write!(f, "{}", error),
+            AttachError::Invalid(error) => write!(f, "{}", error),
+            AttachError::GeometryMismatch(found, index_mode, partitioned) => write!(f, "the shared filter has geometry k = {}, l = {}, w = {}, h = {}, index mode {:?}{}",
+                found.k, found.l, found.w, found.h, index_mode, if *partitioned { ", partitioned rows" } else { "" })
+        }
+    }
+}
+
+impl std::error::Error for AttachError {}
+
+impl From<io::Error> for AttachError {
+    fn from(error: io::Error) -> Self {
+        AttachError::Io(error)
+    }
+}
+
+/// path of a named shared memory segment, a file of the in-memory /dev/shm file system
+pub fn shm_path(name: &str) -> PathBuf {
+    Path::new("/dev/shm").join(name)
+}
+
+/// deletes a shared filter file; processes still attached keep their mapping until they drop it
+pub fn remove(path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::remove_file(path)
+}
+
+#[derive(Debug)]
+pub struct SharedBloom1X {
+    /// number of hashes
+    k: usize,
+    /// number of rows in filter
+    l: usize,
+    /// width of row in bits
+    w: usize,
+    /// number of bits to be extracted from hash for computing the row index
+    row_bits: usize,
+    /// number of bits to be extracted from hash for each sub-hash
+    hash_bits: usize,
+    /// how sub-hashes are mapped onto positions inside a row
+    index_mode: IndexMode,
+    /// whether every sub-hash indexes its own segment of the row
+    partitioned: bool,
+    /// header followed by the rows
+    map: MmapMut,
+    /// start of the mapping, all accesses to the bits go through atomics derived from it
+    base: *mut u8
+}
+
+//the mapping is only ever accessed through atomics, like memory shared with the other processes
+unsafe impl Send for SharedBloom1X {}
+unsafe impl Sync for SharedBloom1X {}
+
+impl SharedBloom1X {
+    /// creates a new shared file holding the geometry and the bits of the filter
+    /// fails if the file already exists, so that two creators never overwrite each other
+    pub fn create(path: impl AsRef<Path>, bloom: &Bloom1X) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+        file.set_len((HEADER_SIZE + bloom.memory_size()) as u64)?;
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+
+        //other processes may map the file already, so it is only written through raw pointers, never through a slice
+        let base = map.as_mut_ptr();
+        let mut header = [0u8; HEADER_SIZE];
+        header[4] = VERSION;
+        for (i, value) in [bloom.k(), bloom.l(), bloom.w(), bloom.h()].into_iter().enumerate() {
+            header[8 + 4 * i..12 + 4 * i].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        header[24] = bloom.index_mode().to_u8();
+        header[25] = bloom.partitioned() as u8;
+        for (i, byte) in header.into_iter().enumerate().skip(4) {
+            unsafe { base.add(i).write_volatile(byte) };
+        }
+        let row_size = bloom.w() / 8;
+        for i in 0..bloom.l() {
+            unsafe { std::ptr::copy_nonoverlapping(bloom.row(i).as_ptr(), base.add(HEADER_SIZE + i * row_size), row_size) };
+        }
+        //publish the filter to the processes polling for it
+        let magic = unsafe { AtomicU32::from_ptr(base as *mut u32) };
+        magic.store(u32::from_le_bytes(*MAGIC), Ordering::Release);
+
+        Self::attach(map, None).map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    /// attaches to a shared filter, checking the header and the size of the file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AttachError> {
+        Self::attach(Self::map(path)?, None)
+    }
+
+    /// attaches to a shared filter that must have the expected geometry, index mode and partitioning,
+    /// so that it hashes keys the way the caller's own filters do
+    pub fn open_with(path: impl AsRef<Path>, expected: FilterParameters, index_mode: IndexMode, partitioned: bool) -> Result<Self, AttachError> {
+        Self::attach(Self::map(path)?, Some((expected, index_mode, partitioned)))
+    }
+
+    fn map(path: impl AsRef<Path>) -> io::Result<MmapMut> {
+        let file: File = OpenOptions::new().read(true).write(true).open(path)?;
+        unsafe { MmapMut::map_mut(&file) }
+    }
+
+    fn attach(mut map: MmapMut, expected: Option<(FilterParameters, IndexMode, bool)>) -> Result<Self, AttachError> {
+        if map.len() < HEADER_SIZE {
+            return Err(AttachError::Invalid(DecodeError::Truncated));
+        }
+        let base = map.as_mut_ptr();
+        let magic = unsafe { AtomicU32::from_ptr(base as *mut u32) };
+        if magic.load(Ordering::Acquire) != u32::from_le_bytes(*MAGIC) {
+            return Err(AttachError::Invalid(DecodeError::BadMagic));
+        }
+        //the header is copied out of the live mapping, the other processes keep writing to the rows behind it
+        let mut header = [0u8; HEADER_SIZE];
+        for (i, byte) in header.iter_mut().enumerate() {
+            *byte = unsafe { base.add(i).read_volatile() };
+        }
+        if header[4] != VERSION {
+            return Err(AttachError::Invalid(DecodeError::UnsupportedVersion(header[4])));
+        }
+        let field = |i: usize| u32::from_le_bytes(header[8 + 4 * i..12 + 4 * i].try_into().unwrap()) as usize;
+        let (k, l, w, h) = (field(0), field(1), field(2), field(3));
+        let index_mode = IndexMode::from_u8(header[24]).ok_or(AttachError::Invalid(DecodeError::InvalidParameters))?;
+        let partitioned = match header[25] {
+            0 => false,
+            1 => true,
+            _ => return Err(AttachError::Invalid(DecodeError::InvalidParameters))
+        };
+        if !index::geometry_is_valid(k, l, w, h) || (partitioned && !w.is_multiple_of(k)) {
+            return Err(AttachError::Invalid(DecodeError::InvalidParameters));
+        }
+        if map.len() != HEADER_SIZE + l * w / 8 {
+            return Err(AttachError::Invalid(DecodeError::Truncated));
+        }
+        let found = (FilterParameters { k, l, w, h }, index_mode, partitioned);
+        if expected.is_some_and(|expected| expected != found) {
+            return Err(AttachError::GeometryMismatch(found.0, index_mode, partitioned));
+        }
+        let row_bits = index::row_bits(k, l, h);
+        let hash_bits = (h - row_bits) / k;
+        Ok(Self { k, l, w, row_bits, hash_bits, index_mode, partitioned, map, base })
+    }
+
+    /// number of hashes
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// number of rows
+    pub fn l(&self) -> usize {
+        self.l
+    }
+
+    /// width of a row in bits
+    pub fn w(&self) -> usize {
+        self.w
+    }
+
+    /// number of hash bits used per key
+    pub fn h(&self) -> usize {
+        self.row_bits + self.k * self.hash_bits
+    }
+
+    /// number of bytes used by the bits of the filter, without the header
+    pub fn memory_size(&self) -> usize {
+        self.l * self.w / 8
+    }
+
+    /// byte of the rows as an atomic shared with the other processes
+    #[inline(always)]
+    fn byte(&self, row_index: usize, bit_index: usize) -> &AtomicU8 {
+        let offset = HEADER_SIZE + row_index * self.w / 8 + bit_index / 8;
+        //AtomicU8 has the layout of u8 and the mapping outlives the returned reference
+        unsafe { AtomicU8::from_ptr(self.base.add(offset)) }
+    }
+
+    /// row index and bit indexes of a key, the same ones a Bloom1X of this geometry uses
+    fn locate(&self, digest: &KeyDigest) -> (usize, Vec<usize>) {
+        let row_index = index::row_index(&digest.0, self.l, self.row_bits);
+        let sub_hashes = index::sub_hashes(&digest.0, self.row_bits);
+        let bit_indexes = (0..self.k)
+            .map(|i| index::row_position(sub_hashes, i, self.hash_bits, self.w, self.k, self.index_mode, self.partitioned))
+            .collect();
+        (row_index, bit_indexes)
+    }
+
+    /// returns 0x1 if the key behind the digest was found inside the filter and 0x0 otherwise
+    pub fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        let (row_index, bit_indexes) = self.locate(digest);
+        let mut and_result = 1;
+        for bit_index in bit_indexes {
+            and_result &= (self.byte(row_index, bit_index).load(Ordering::Acquire) >> (bit_index % 8)) & 1;
+        }
+        and_result
+    }
+
+    /// sets the bits of the key behind the digest, returning its membership before the insertion
+    /// two processes inserting the same new key at once may both see 0x0
+    pub fn insert_digest(&self, digest: &KeyDigest) -> u8 {
+        let (row_index, bit_indexes) = self.locate(digest);
+        let mut and_result = 1;
+        for bit_index in bit_indexes {
+            let previous = self.byte(row_index, bit_index).fetch_or(1 << (bit_index % 8), Ordering::AcqRel);
+            and_result &= (previous >> (bit_index % 8)) & 1;
+        }
+        and_result
+    }
+
+    pub fn query_bytes(&self, bytes: &[u8]) -> u8 {
+        self.contains_digest(&digest::hash_bytes(bytes))
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> u8 {
+        self.contains_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> u8 {
+        self.contains_digest(&digest::hash_u64(bytes))
+    }
+
+    /// inserts take a shared reference, the bits are only ever changed atomically
+    pub fn query_and_set_bytes(&self, bytes: &[u8]) -> u8 {
+        self.insert_digest(&digest::hash_bytes(bytes))
+    }
+
+    pub fn query_and_set_u32(&self, bytes: u32) -> u8 {
+        self.insert_digest(&digest::hash_u32(bytes))
+    }
+
+    pub fn query_and_set_u64(&self, bytes: u64) -> u8 {
+        self.insert_digest(&digest::hash_u64(bytes))
+    }
+
+    /// clears every bit, keys inserted concurrently by other processes may survive partially
+    pub fn clear(&self) {
+        for i in 0..self.l {
+            for j in (0..self.w).step_by(8) {
+                self.byte(i, j).store(0, Ordering::Release);
+            }
+        }
+    }
+
+    /// copies the current bits into a private Bloom1X
+    pub fn to_bloom1x(&self) -> Bloom1X {
+        let mut bloom = Bloom1X::new(self.k, self.l, self.w, self.h()).with_index_mode(self.index_mode);
+        if self.partitioned {
+            bloom = bloom.with_partitioned_rows();
+        }
+        for i in 0..self.l {
+            for j in (0..self.w).step_by(8) {
+                bloom.filter[i][j / 8] = self.byte(i, j).load(Ordering::Acquire);
+            }
+        }
+        bloom
+    }
+
+    /// writes the bits back to the file, only needed for files that must survive a reboot
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl MembershipFilter for SharedBloom1X {
+    fn insert_bytes(&mut self, bytes: &[u8]) -> u8 {
+        self.query_and_set_bytes(bytes)
+    }
+
+    fn insert_u64(&mut self, bytes: u64) -> u8 {
+        self.query_and_set_u64(bytes)
+    }
+
+    fn contains_bytes(&self, bytes: &[u8]) -> u8 {
+        self.query_bytes(bytes)
+    }
+
+    fn contains_u64(&self, bytes: u64) -> u8 {
+        self.query_u64(bytes)
+    }
+
+    fn insert_digest(&mut self, digest: &KeyDigest) -> u8 {
+        SharedBloom1X::insert_digest(self, digest)
+    }
+
+    fn contains_digest(&self, digest: &KeyDigest) -> u8 {
+        SharedBloom1X::contains_digest(self, digest)
+    }
+
+    fn clear(&mut self) {
+        SharedBloom1X::clear(self)
+    }
+
+    fn parameters(&self) -> FilterParameters {
+        FilterParameters { k: self.k, l: self.l, w: self.w, h: self.h() }
+    }
+
+    fn memory_size(&self) -> usize {
+        SharedBloom1X::memory_size(self)
+    }
+
+    /// the encoding of a Bloom1X with the current bits
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_bloom1x().to_bytes()
+    }
+}
@@ -0,0 +1,142 @@
+//! stable Bloom filter built on the row layout of Bloom1Counter
+//! every insertion first decrements P random counters of the row it hits and then
+//! sets the k counters of the key to the maximum value, so old keys slowly fade out
+//! and the false positive rate levels off instead of climbing to 1 on unbounded streams
+
+use ::xoodoo_hash::xoodoo_hash::{xoodoo_state::{XoodooStateNC}, XoodooHash};
+use crate::bloom_counter::{Bloom1Counter, CounterResult, CounterWidth};
+
+#[derive(Debug)]
+pub struct StableBloom1 {
+    /// counters of the filter
+    counters: Bloom1Counter,
+    /// number of hashes
+    k: usize,
+    /// number of counters per row
+    w: usize,
+    /// value given to the counters of an inserted key
+    max: u16,
+    /// number of random counters decremented in the row of every insertion
+    p: usize,
+    /// state of the xorshift generator choosing the decremented counters
+    rng: u64
+}
+
+/// limit of the false positive rate of a stable Bloom filter
+/// every counter of a row holding w counters is 0 with probability
+/// P0 = (1 / (1 + 1 / (p * (1/k - 1/w))))^max once the filter is stable, and a query fails
+/// only if its k counters are all non-zero, i.e. FPR = (1 - P0)^k (Deng & Rafiei, 2006)
+/// keeping the decrements in the row of the insertion does not change the limit since
+/// every row is an independent copy of the same process
+pub fn stable_false_positive_rate(k: usize, w: usize, max: u16, p: usize) -> f64 {
+    let (k, w, p) = (k as f64, w as f64, p as f64);
+    let p0 = f64::powf(1.0 / (1.0 + 1.0 / (p * (1.0 / k - 1.0 / w))), max as f64);
+    f64::powf(1.0 - p0, k)
+}
+
+impl StableBloom1 {
+    /// w -> number of counters per row
+    /// l -> number of rows
+    /// h -> size of hash function output in bits
+    /// max -> value given to the counters of an inserted key, sizing the counters
+    /// p -> number of counters decremented in the row of every insertion
+    pub fn new(k: usize, l: usize, w: usize, h: usize, max: u16, p: usize) -> Self {
+        assert!(max >= 1);
+        assert!(p >= 1 && p <= w);
+        let width = [CounterWidth::Bits2, CounterWidth::Bits4, CounterWidth::Bits8, CounterWidth::Bits16]
+            .into_iter()
+            .find(|width| width.max() >= max)
+            .unwrap();
+        let counters = Bloom1Counter::new(k, l, w, h).with_counter_width(width);
+        Self { counters, k, w, max, p, rng: 0x9E37_79B9_7F4A_7C15 }
+    }
+
+    /// seeds the generator choosing the decremented counters
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = seed | 1;
+        self
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.clear();
+    }
+
+    /// false positive rate the filter converges to, see stable_false_positive_rate
+    pub fn predicted_false_positive_rate(&self) -> f64 {
+        stable_false_positive_rate(self.k, self.w, self.max, self.p)
+    }
+
+    /// number of bytes used by the counters of the filter
+    pub fn memory_size(&self) -> usize {
+        self.counters.memory_size()
+    }
+
+    /// the query functions check if a provided key is member of the filter
+    /// returns 0x1 if the provided bytes were found inside the filter and 0x0 otherwise
+    pub fn query_bytes(&self, bytes: &[u8]) -> u8 {
+        self.counters.query_bytes(bytes)
+    }
+
+    pub fn query_u32(&self, bytes: u32) -> u8 {
+        self.counters.query_u32(bytes)
+    }
+
+    pub fn query_u64(&self, bytes: u64) -> u8 {
+        self.counters.query_u64(bytes)
+    }
+
+    /// next position inside a row, drawn with xorshift64*
+    #[inline(always)]
+    fn random_counter(&mut self) -> usize {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let random = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32;
+        ((random * self.w as u64) >> 32) as usize
+    }
+
+    /// decrements p random counters of the row of the key and sets the counters of the key to max
+    #[inline(always)]
+    fn insert(&mut self, qr: &CounterResult) {
+        for _ in 0..self.p {
+            let counter_index = self.random_counter();
+            let counter = self.counters.counter(qr.row_index, counter_index);
+            if counter > 0 {
+                self.counters.set_counter(qr.row_index, counter_index, counter - 1);
+            }
+        }
+        for i in 0..self.k {
+            self.counters.set_counter(qr.row_index, qr.counter_indexes[i], self.max);
+        }
+    }
+
+    /// this functions queris the memebership returning the true/false response
+    /// and also inserts the key
+    pub fn query_and_set_bytes(&mut self, bytes: &[u8]) -> u8 {
+        assert!(bytes.len() <= 48); //limit to 48 bytes
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_bytes(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.counters.parse_hash(&digest);
+        self.insert(&query_result);
+        query_result.and_result
+    }
+
+    pub fn query_and_set_u32(&mut self, bytes: u32) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u32(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.counters.parse_hash(&digest);
+        self.insert(&query_result);
+        query_result.and_result
+    }
+
+    pub fn query_and_set_u64(&mut self, bytes: u64) -> u8 {
+        let mut hasher = XoodooHash::<XoodooStateNC>::new_from_u64(bytes);
+        hasher.permute_nc();
+        let digest = hasher.digest_nc();
+        let query_result = self.counters.parse_hash(&digest);
+        self.insert(&query_result);
+        query_result.and_result
+    }
+}